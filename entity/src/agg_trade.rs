@@ -0,0 +1,69 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use std::fmt::Write;
+
+use crate::{
+  bulk::{copy_staged, create_month_partition},
+  Market,
+};
+
+/// Trades filled by the same taker order at the same price, as archived by binance.
+#[derive(Serialize, specta::Type)]
+pub struct AggTrade {
+  pub market: Market,
+  pub symbol: String,
+  pub agg_trade_id: i64,
+  pub price: Decimal,
+  pub quantity: Decimal,
+  pub first_trade_id: i64,
+  pub last_trade_id: i64,
+  /// Milliseconds
+  pub transact_time: i64,
+  pub is_buyer_maker: bool,
+}
+
+impl AggTrade {
+  /// Creates the partition of `agg_trades` holding the trades of `month`.
+  /// Not safe to run concurrently for the same month.
+  pub async fn ensure_partition(pool: &PgPool, month: NaiveDate) -> Result<()> {
+    create_month_partition(pool, "agg_trades", month).await
+  }
+
+  /// Streams the trades through `COPY` into a staging table and moves them into
+  /// `agg_trades`, skipping existing ones. Returns the number of rows written.
+  pub async fn bulk_insert(conn: &mut PgConnection, trades: &[Self]) -> Result<u64> {
+    if trades.is_empty() {
+      return Ok(0);
+    }
+
+    let mut csv = String::new();
+    for t in trades {
+      writeln!(
+        csv,
+        "{},{},{},{},{},{},{},{},{}",
+        t.market,
+        t.symbol,
+        t.agg_trade_id,
+        t.price,
+        t.quantity,
+        t.first_trade_id,
+        t.last_trade_id,
+        t.transact_time,
+        t.is_buyer_maker
+      )?;
+    }
+
+    copy_staged(
+      conn,
+      "agg_trades",
+      "market, symbol, agg_trade_id, price, quantity, first_trade_id, last_trade_id, \
+        transact_time, is_buyer_maker",
+      csv,
+      "ON CONFLICT DO NOTHING",
+    )
+    .await
+  }
+}
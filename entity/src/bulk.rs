@@ -0,0 +1,89 @@
+//! Shared by the tables that are partitioned by month and filled from archives.
+
+use anyhow::Result;
+use chrono::{Months, NaiveDate};
+use sqlx::{PgConnection, PgPool};
+
+/// Creates the partition of `table` holding the rows of `month`, which must be
+/// partitioned by a millisecond timestamp. Not safe to run concurrently for the
+/// same month.
+pub(crate) async fn create_month_partition(
+  pool: &PgPool,
+  table: &str,
+  month: NaiveDate,
+) -> Result<()> {
+  let next = month + Months::new(1);
+  let (from, to) = (month_millis(month), month_millis(next));
+
+  // Partitions are created on demand, so these can't use query!
+  sqlx::query(&format!(
+    r#"--sql
+CREATE TABLE IF NOT EXISTS {table}_{}
+PARTITION OF {table} FOR VALUES FROM ( {from} ) TO ( {to} );
+    "#,
+    month.format("%Y_%m")
+  ))
+  .execute(pool)
+  .await?;
+
+  Ok(())
+}
+
+/// Streams `csv` rows of `columns` through `COPY` into a staging table shaped like
+/// `table`, then moves them into `table` resolving duplicates with `on_conflict`.
+/// Returns the number of rows written.
+pub(crate) async fn copy_staged(
+  conn: &mut PgConnection,
+  table: &str,
+  columns: &str,
+  csv: String,
+  on_conflict: &str,
+) -> Result<u64> {
+  // The staging table doesn't exist at compile time, so these can't use query!
+  sqlx::query(&format!(
+    r#"--sql
+CREATE TEMP TABLE IF NOT EXISTS {table}_staging
+( LIKE {table} INCLUDING DEFAULTS )
+ON COMMIT DELETE ROWS;
+    "#
+  ))
+  .execute(&mut *conn)
+  .await?;
+
+  let mut copy = conn
+    .copy_in_raw(&format!(
+      r#"--sql
+COPY {table}_staging ( {columns} )
+FROM STDIN WITH ( FORMAT csv );
+      "#
+    ))
+    .await?;
+  copy.send(csv.into_bytes()).await?;
+  copy.finish().await?;
+
+  let written = sqlx::query(&format!(
+    r#"--sql
+INSERT INTO {table}
+SELECT * FROM {table}_staging
+{on_conflict};
+    "#
+  ))
+  .execute(&mut *conn)
+  .await?
+  .rows_affected();
+
+  sqlx::query(&format!("TRUNCATE {table}_staging;"))
+    .execute(&mut *conn)
+    .await?;
+
+  Ok(written)
+}
+
+/// The first millisecond of a month, in UTC.
+pub fn month_millis(month: NaiveDate) -> i64 {
+  month
+    .and_hms_opt(0, 0, 0)
+    .unwrap()
+    .and_utc()
+    .timestamp_millis()
+}
@@ -1,20 +1,43 @@
 use anyhow::Result;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use sqlx::{query, PgConnection};
+use sqlx::{query, query_as, PgConnection, PgPool};
+use std::fmt::Write;
 
-#[derive(Deserialize, Serialize, Default)]
+use crate::{
+  bulk::{copy_staged, create_month_partition},
+  Interval, Market,
+};
+
+#[derive(Deserialize, Serialize, Default, specta::Type)]
 #[serde(default)]
 pub struct Candle {
+  pub market: Market,
   pub symbol: String,
-  pub interval: String,
+  pub interval: Interval,
   pub open_time: i64,
-  pub open: f32,
-  pub close: f32,
-  pub high: f32,
-  pub low: f32,
+  /// Missing on candles loaded before it was kept
+  pub close_time: Option<i64>,
+  pub open: Decimal,
+  pub close: Decimal,
+  pub high: Decimal,
+  pub low: Decimal,
   pub num_trades: i32,
-  pub volume: f32,
-  pub taker_volume: f32,
+  pub volume: Decimal,
+  pub taker_volume: Decimal,
+  pub quote_volume: Option<Decimal>,
+  pub taker_quote_volume: Option<Decimal>,
+  /// The interval the candle was built from, `None` when loaded from binance
+  pub resampled_from: Option<Interval>,
+}
+
+/// The span of stored candles for one interval of a symbol.
+#[derive(Serialize, specta::Type)]
+pub struct CandleCoverage {
+  pub interval: Interval,
+  pub first_open_time: i64,
+  pub last_open_time: i64,
 }
 
 impl Candle {
@@ -22,24 +45,286 @@ impl Candle {
     query!(
       r#"--sql
 INSERT INTO candles
-( symbol, interval, open_time, open, close, high, low, num_trades, volume, taker_volume )
-VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10 )
-ON CONFLICT ( symbol, "interval", open_time ) DO NOTHING;
+( market, symbol, interval, open_time, close_time, open, close, high, low, num_trades,
+  volume, taker_volume, quote_volume, taker_quote_volume, resampled_from )
+VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15 )
+ON CONFLICT ( market, symbol, "interval", open_time ) DO NOTHING;
       "#,
+      self.market.as_str(),
       self.symbol,
-      self.interval,
+      self.interval.to_string(),
       self.open_time,
+      self.close_time,
       self.open,
       self.close,
       self.high,
       self.low,
       self.num_trades,
       self.volume,
-      self.taker_volume
+      self.taker_volume,
+      self.quote_volume,
+      self.taker_quote_volume,
+      self.resampled_from.map(|i| i.to_string())
     )
     .execute(pool)
     .await?;
 
     Ok(())
   }
+
+  /// Creates the partition of `candles` holding the candles opened in `month`.
+  /// Does nothing when `candles` is a TimescaleDB hypertable, which creates its
+  /// own chunks. Not safe to run concurrently for the same month.
+  pub async fn ensure_partition(pool: &PgPool, month: NaiveDate) -> Result<()> {
+    let partitioned = query!(
+      r#"--sql
+SELECT EXISTS (
+  SELECT 1 FROM pg_partitioned_table p WHERE p.partrelid = 'candles'::regclass
+) AS "partitioned!";
+      "#
+    )
+    .fetch_one(pool)
+    .await?
+    .partitioned;
+    if !partitioned {
+      return Ok(());
+    }
+
+    create_month_partition(pool, "candles", month).await
+  }
+
+  /// Streams candles loaded from binance through `COPY` into a staging table and
+  /// moves them into `candles`. Existing candles are skipped, unless they were
+  /// resampled or loaded before `close_time` was kept: those also predate exact
+  /// prices, which were rounded through `REAL`, so they are rewritten whole.
+  /// Returns the number of rows written.
+  pub async fn bulk_insert(conn: &mut PgConnection, candles: &[Self]) -> Result<u64> {
+    copy_candles(
+      conn,
+      candles,
+      r#"
+ON CONFLICT ( market, symbol, "interval", open_time ) DO UPDATE
+SET open = EXCLUDED.open,
+  high = EXCLUDED.high,
+  low = EXCLUDED.low,
+  close = EXCLUDED.close,
+  volume = EXCLUDED.volume,
+  taker_volume = EXCLUDED.taker_volume,
+  num_trades = EXCLUDED.num_trades,
+  close_time = EXCLUDED.close_time,
+  quote_volume = EXCLUDED.quote_volume,
+  taker_quote_volume = EXCLUDED.taker_quote_volume,
+  resampled_from = EXCLUDED.resampled_from
+WHERE candles.close_time IS NULL OR candles.resampled_from IS NOT NULL
+      "#,
+    )
+    .await
+  }
+
+  /// Like `bulk_insert` for resampled candles, which replace earlier resampled
+  /// candles but never ones loaded from binance.
+  pub async fn bulk_insert_resampled(conn: &mut PgConnection, candles: &[Self]) -> Result<u64> {
+    copy_candles(
+      conn,
+      candles,
+      r#"
+ON CONFLICT ( market, symbol, "interval", open_time ) DO UPDATE
+SET open = EXCLUDED.open,
+  high = EXCLUDED.high,
+  low = EXCLUDED.low,
+  close = EXCLUDED.close,
+  volume = EXCLUDED.volume,
+  taker_volume = EXCLUDED.taker_volume,
+  num_trades = EXCLUDED.num_trades,
+  close_time = EXCLUDED.close_time,
+  quote_volume = EXCLUDED.quote_volume,
+  taker_quote_volume = EXCLUDED.taker_quote_volume,
+  resampled_from = EXCLUDED.resampled_from
+WHERE candles.resampled_from IS NOT NULL
+      "#,
+    )
+    .await
+  }
+
+  /// Candles for a symbol and interval ordered by `open_time`.
+  /// `from` and `to` are inclusive millisecond timestamps.
+  pub async fn fetch_range(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+    interval: Interval,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: i64,
+  ) -> Result<Vec<Self>> {
+    let candles = query_as!(
+      Self,
+      r#"--sql
+SELECT
+  c.market AS "market: Market", c.symbol, c.interval AS "interval: Interval", c.open_time, c.close_time,
+  c.open, c.close, c.high, c.low, c.num_trades, c.volume, c.taker_volume,
+  c.quote_volume, c.taker_quote_volume, c.resampled_from AS "resampled_from: Interval"
+FROM candles c
+WHERE c.market = $1
+  AND c.symbol = $2
+  AND c.interval = $3
+  AND ( $4::BIGINT IS NULL OR c.open_time >= $4 )
+  AND ( $5::BIGINT IS NULL OR c.open_time <= $5 )
+ORDER BY c.open_time
+LIMIT $6;
+      "#,
+      market.as_str(),
+      symbol,
+      interval.to_string(),
+      from,
+      to,
+      limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(candles)
+  }
+
+  /// Whether any candles of the interval are stored for the symbol.
+  pub async fn has_interval(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+    interval: Interval,
+  ) -> Result<bool> {
+    let row = query!(
+      r#"--sql
+SELECT EXISTS (
+  SELECT 1 FROM candles c WHERE c.market = $1 AND c.symbol = $2 AND c.interval = $3
+) AS "exists!";
+      "#,
+      market.as_str(),
+      symbol,
+      interval.to_string()
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.exists)
+  }
+
+  /// The stored intervals of a symbol, finest first, with their first and last candles.
+  /// Walks the primary key one interval at a time, so it stays cheap on a symbol with
+  /// years of 1s candles.
+  pub async fn coverage(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+  ) -> Result<Vec<CandleCoverage>> {
+    let mut coverage = query_as!(
+      CandleCoverage,
+      r#"--sql
+WITH RECURSIVE intervals AS (
+  SELECT MIN(c.interval) AS interval
+  FROM candles c
+  WHERE c.market = $1 AND c.symbol = $2
+  UNION ALL
+  SELECT (
+    SELECT MIN(c.interval)
+    FROM candles c
+    WHERE c.market = $1 AND c.symbol = $2 AND c.interval > i.interval
+  )
+  FROM intervals i
+  WHERE i.interval IS NOT NULL
+)
+SELECT
+  i.interval AS "interval!: Interval",
+  (
+    SELECT MIN(c.open_time) FROM candles c
+    WHERE c.market = $1 AND c.symbol = $2 AND c.interval = i.interval
+  ) AS "first_open_time!",
+  (
+    SELECT MAX(c.open_time) FROM candles c
+    WHERE c.market = $1 AND c.symbol = $2 AND c.interval = i.interval
+  ) AS "last_open_time!"
+FROM intervals i
+WHERE i.interval IS NOT NULL;
+      "#,
+      market.as_str(),
+      symbol
+    )
+    .fetch_all(pool)
+    .await?;
+
+    // Interval names don't sort by length, `12h` < `15m` < `1d`.
+    coverage.sort_by_key(|c| c.interval.max_millis());
+    Ok(coverage)
+  }
+
+  /// How many candles of the interval are stored for the symbol.
+  pub async fn count(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+    interval: Interval,
+  ) -> Result<i64> {
+    let row = query!(
+      r#"--sql
+SELECT COUNT(*) AS "count!"
+FROM candles c
+WHERE c.market = $1 AND c.symbol = $2 AND c.interval = $3;
+      "#,
+      market.as_str(),
+      symbol,
+      interval.to_string()
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.count)
+  }
+}
+
+async fn copy_candles(
+  conn: &mut PgConnection,
+  candles: &[Candle],
+  on_conflict: &str,
+) -> Result<u64> {
+  if candles.is_empty() {
+    return Ok(0);
+  }
+
+  let mut csv = String::new();
+  for c in candles {
+    writeln!(
+      csv,
+      "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+      c.market,
+      c.symbol,
+      c.interval,
+      c.open_time,
+      csv_opt(c.close_time),
+      c.open,
+      c.close,
+      c.high,
+      c.low,
+      c.num_trades,
+      c.volume,
+      c.taker_volume,
+      csv_opt(c.quote_volume),
+      csv_opt(c.taker_quote_volume),
+      csv_opt(c.resampled_from)
+    )?;
+  }
+
+  copy_staged(
+    conn,
+    "candles",
+    "market, symbol, interval, open_time, close_time, open, close, high, low, num_trades, \
+      volume, taker_volume, quote_volume, taker_quote_volume, resampled_from",
+    csv,
+    on_conflict,
+  )
+  .await
+}
+
+/// An empty field is NULL in `COPY ... ( FORMAT csv )`.
+fn csv_opt<T: std::fmt::Display>(value: Option<T>) -> String {
+  value.map(|v| v.to_string()).unwrap_or_default()
 }
@@ -0,0 +1,243 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sqlx::{query, query_as, PgPool};
+
+use crate::{Candle, Interval, Market};
+
+/// Bounds the rows each check returns.
+pub const AUDIT_LIMIT: i64 = 1000;
+
+/// Consecutive candles further apart than their interval.
+#[derive(Serialize, specta::Type)]
+pub struct CandleGap {
+  /// `open_time` of the candle before the gap
+  pub after: i64,
+  /// `open_time` of the candle after the gap
+  pub before: i64,
+}
+
+/// A candle breaking an OHLC or volume invariant.
+#[derive(Serialize, specta::Type)]
+pub struct InvalidCandle {
+  pub interval: Interval,
+  pub open_time: i64,
+  pub problems: Vec<String>,
+}
+
+/// Identical candles stored under two intervals, usually an archive loaded
+/// under the wrong interval.
+#[derive(Serialize, specta::Type)]
+pub struct DuplicateCandle {
+  pub interval: Interval,
+  pub other_interval: Interval,
+  pub open_time: i64,
+}
+
+/// A candle whose volume isn't the sum of the finer candles it spans.
+#[derive(Serialize, specta::Type)]
+pub struct VolumeMismatch {
+  pub interval: Interval,
+  pub fine_interval: Interval,
+  pub open_time: i64,
+  pub volume: Decimal,
+  pub fine_volume: Decimal,
+}
+
+/// Checks over the stored candles of a symbol. Each returns at most `AUDIT_LIMIT` rows.
+impl Candle {
+  /// Where consecutive candles of an interval are further apart than their length in UTC.
+  pub async fn gaps(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+    interval: Interval,
+  ) -> Result<Vec<CandleGap>> {
+    let gaps = query_as!(
+      CandleGap,
+      r#"--sql
+SELECT g.after AS "after!", g.before AS "before!"
+FROM (
+  SELECT
+    c.open_time AS after,
+    LEAD(c.open_time) OVER ( ORDER BY c.open_time ) AS before
+  FROM candles c
+  WHERE c.market = $1 AND c.symbol = $2 AND c.interval = $3
+) g
+WHERE g.before - g.after > $4
+ORDER BY g.after
+LIMIT $5;
+      "#,
+      market.as_str(),
+      symbol,
+      interval.to_string(),
+      interval
+        .alignment()
+        .map_or(interval.max_millis(), |(step, _)| step),
+      AUDIT_LIMIT
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(gaps)
+  }
+
+  /// `open_time`s that aren't the interval's alignment offset plus a multiple of its
+  /// step in ms, or the start of a UTC month for months.
+  pub async fn misaligned(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+    interval: Interval,
+  ) -> Result<Vec<i64>> {
+    let (step, offset) = interval.alignment().unzip();
+    let rows = query!(
+      r#"--sql
+SELECT c.open_time
+FROM candles c
+WHERE c.market = $1 AND c.symbol = $2 AND c.interval = $3
+  AND CASE
+    WHEN $4::BIGINT IS NULL THEN
+      date_trunc('month', to_timestamp(c.open_time / 1000.0) AT TIME ZONE 'UTC')
+        <> to_timestamp(c.open_time / 1000.0) AT TIME ZONE 'UTC'
+    ELSE ( c.open_time - $5::BIGINT ) % $4 <> 0
+  END
+ORDER BY c.open_time
+LIMIT $6;
+      "#,
+      market.as_str(),
+      symbol,
+      interval.to_string(),
+      step,
+      offset,
+      AUDIT_LIMIT
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| r.open_time).collect())
+  }
+
+  /// Candles with a low above their open or close, a high below them,
+  /// or negative volumes and trade counts.
+  pub async fn invalid(pool: &PgPool, market: Market, symbol: &str) -> Result<Vec<InvalidCandle>> {
+    let invalid = query_as!(
+      InvalidCandle,
+      r#"--sql
+SELECT i.interval AS "interval!: Interval", i.open_time AS "open_time!", i.problems AS "problems!"
+FROM (
+  SELECT c.interval, c.open_time, ARRAY_REMOVE(ARRAY[
+    CASE WHEN c.low > c.open THEN 'low > open' END,
+    CASE WHEN c.low > c.close THEN 'low > close' END,
+    CASE WHEN c.high < c.open THEN 'high < open' END,
+    CASE WHEN c.high < c.close THEN 'high < close' END,
+    CASE WHEN c.volume < 0 THEN 'negative volume' END,
+    CASE WHEN c.taker_volume < 0 THEN 'negative taker volume' END,
+    CASE WHEN c.taker_volume > c.volume THEN 'taker volume > volume' END,
+    CASE WHEN c.quote_volume < 0 THEN 'negative quote volume' END,
+    CASE WHEN c.num_trades < 0 THEN 'negative trades' END,
+    CASE WHEN c.close_time <= c.open_time THEN 'close_time <= open_time' END
+  ], NULL) AS problems
+  FROM candles c
+  WHERE c.market = $1 AND c.symbol = $2
+) i
+WHERE cardinality(i.problems) > 0
+ORDER BY i.interval, i.open_time
+LIMIT $3;
+      "#,
+      market.as_str(),
+      symbol,
+      AUDIT_LIMIT
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(invalid)
+  }
+
+  /// Candles with trades that are identical to a candle of another interval.
+  pub async fn duplicates(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+  ) -> Result<Vec<DuplicateCandle>> {
+    let duplicates = query_as!(
+      DuplicateCandle,
+      r#"--sql
+SELECT
+  a.interval AS "interval: Interval",
+  b.interval AS "other_interval: Interval",
+  a.open_time
+FROM candles a
+JOIN candles b
+  ON b.market = a.market
+  AND b.symbol = a.symbol
+  AND b.open_time = a.open_time
+  AND b.interval > a.interval
+WHERE a.market = $1 AND a.symbol = $2
+  AND a.num_trades > 0
+  AND ( a.open, a.high, a.low, a.close, a.volume, a.num_trades )
+    = ( b.open, b.high, b.low, b.close, b.volume, b.num_trades )
+ORDER BY a.open_time
+LIMIT $3;
+      "#,
+      market.as_str(),
+      symbol,
+      AUDIT_LIMIT
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(duplicates)
+  }
+
+  /// Candles of `interval` whose volume differs from the sum of the `fine_interval`
+  /// candles they span. Only candles with all their finer candles stored are compared,
+  /// gaps are reported separately. Resampled candles, which are such sums, and candles
+  /// loaded before `close_time` was kept are skipped, and nothing is compared when
+  /// `fine_interval` is months.
+  pub async fn volume_mismatches(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+    interval: Interval,
+    fine_interval: Interval,
+  ) -> Result<Vec<VolumeMismatch>> {
+    let Some((fine_step, _)) = fine_interval.alignment() else {
+      return Ok(vec![]);
+    };
+    let mismatches = query_as!(
+      VolumeMismatch,
+      r#"--sql
+SELECT
+  c.interval AS "interval: Interval",
+  f.interval AS "fine_interval: Interval",
+  c.open_time,
+  c.volume,
+  SUM(f.volume) AS "fine_volume!"
+FROM candles c
+JOIN candles f
+  ON f.market = c.market
+  AND f.symbol = c.symbol
+  AND f.interval = $4
+  AND f.open_time BETWEEN c.open_time AND c.close_time
+WHERE c.market = $1 AND c.symbol = $2 AND c.interval = $3 AND c.resampled_from IS NULL
+GROUP BY c.interval, f.interval, c.open_time, c.close_time, c.volume
+HAVING COUNT(*) = ( c.close_time + 1 - c.open_time ) / $5
+  AND SUM(f.volume) <> c.volume
+ORDER BY c.open_time
+LIMIT $6;
+      "#,
+      market.as_str(),
+      symbol,
+      interval.to_string(),
+      fine_interval.to_string(),
+      fine_step,
+      AUDIT_LIMIT
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(mismatches)
+  }
+}
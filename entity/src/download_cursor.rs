@@ -0,0 +1,69 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{query, query_as, PgPool};
+
+use crate::Market;
+
+/// Remembers how far the monthly archives of a symbol/interval have been downloaded,
+/// so incremental runs only fetch new files.
+pub struct DownloadCursor {
+  pub market: Market,
+  pub symbol: String,
+  /// An `Interval`, or `aggTrades` for trade archives
+  pub interval: String,
+  /// The first day of the last month whose archive was fetched or confirmed absent
+  pub fetched_through: NaiveDate,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl DownloadCursor {
+  pub async fn find(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+    interval: &str,
+  ) -> Result<Option<Self>> {
+    let cursor = query_as!(
+      Self,
+      r#"--sql
+SELECT
+  dc.market AS "market: Market", dc.symbol, dc.interval, dc.fetched_through, dc.updated_at
+FROM download_cursors dc
+WHERE dc.market = $1 AND dc.symbol = $2 AND dc.interval = $3;
+      "#,
+      market.as_str(),
+      symbol,
+      interval
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(cursor)
+  }
+
+  pub async fn save(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+    interval: &str,
+    fetched_through: NaiveDate,
+  ) -> Result<()> {
+    query!(
+      r#"--sql
+INSERT INTO download_cursors
+( market, symbol, interval, fetched_through )
+VALUES ( $1, $2, $3, $4 )
+ON CONFLICT ( market, symbol, "interval" ) DO UPDATE
+SET fetched_through = EXCLUDED.fetched_through, updated_at = NOW();
+      "#,
+      market.as_str(),
+      symbol,
+      interval,
+      fetched_through
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+  }
+}
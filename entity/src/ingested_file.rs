@@ -0,0 +1,107 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{query, query_as, PgConnection, PgPool};
+
+pub const INGESTED_LOADED: &str = "loaded";
+pub const INGESTED_FAILED: &str = "failed";
+
+/// Records the outcome of loading a history archive,
+/// so reruns skip loaded files and retry failed ones.
+pub struct IngestedFile {
+  /// Relative to the working directory, `history/BTCUSDT/1h/2024-01.zip`
+  pub path: String,
+  /// SHA-256 of the archive that was loaded
+  pub checksum: String,
+  pub row_count: i64,
+  // possible values: loaded, failed
+  pub status: String,
+  pub error: Option<String>,
+  pub loaded_at: DateTime<Utc>,
+}
+
+impl IngestedFile {
+  pub fn is_loaded(&self, checksum: &str) -> bool {
+    self.status == INGESTED_LOADED && self.checksum == checksum
+  }
+
+  pub async fn find(pool: &PgPool, path: &str) -> Result<Option<Self>> {
+    let file = query_as!(
+      Self,
+      r#"--sql
+SELECT * FROM ingested_files f WHERE f.path = $1;
+      "#,
+      path
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(file)
+  }
+
+  pub async fn fetch_all(pool: &PgPool) -> Result<Vec<Self>> {
+    let files = query_as!(
+      Self,
+      r#"--sql
+SELECT * FROM ingested_files f ORDER BY f.path;
+      "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(files)
+  }
+
+  /// Call in the same transaction that loaded the rows.
+  pub async fn mark_loaded(
+    conn: &mut PgConnection,
+    path: &str,
+    checksum: &str,
+    row_count: i64,
+  ) -> Result<()> {
+    query!(
+      r#"--sql
+INSERT INTO ingested_files
+( path, checksum, row_count, status )
+VALUES ( $1, $2, $3, $4 )
+ON CONFLICT ( path ) DO UPDATE
+SET checksum = EXCLUDED.checksum,
+  row_count = EXCLUDED.row_count,
+  status = EXCLUDED.status,
+  error = NULL,
+  loaded_at = NOW();
+      "#,
+      path,
+      checksum,
+      row_count,
+      INGESTED_LOADED
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+  }
+
+  pub async fn mark_failed(pool: &PgPool, path: &str, checksum: &str, error: &str) -> Result<()> {
+    query!(
+      r#"--sql
+INSERT INTO ingested_files
+( path, checksum, row_count, status, error )
+VALUES ( $1, $2, 0, $3, $4 )
+ON CONFLICT ( path ) DO UPDATE
+SET checksum = EXCLUDED.checksum,
+  row_count = 0,
+  status = EXCLUDED.status,
+  error = EXCLUDED.error,
+  loaded_at = NOW();
+      "#,
+      path,
+      checksum,
+      INGESTED_FAILED,
+      error
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,760 @@
+use anyhow::{anyhow, bail, Error};
+use chrono::{DateTime, Datelike, Months, NaiveDate, Offset, TimeDelta, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::{
+  encode::IsNull,
+  error::BoxDynError,
+  postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+  Decode, Encode, Postgres,
+};
+use std::{fmt, str::FromStr};
+
+const SECOND: i64 = 1000;
+const MINUTE: i64 = 60 * SECOND;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+
+/// The longest interval `FromStr` accepts, so that spans and calendar arithmetic
+/// stay far from overflowing.
+const MAX_YEARS: u32 = 100;
+const MAX_DAYS: u32 = MAX_YEARS * 366;
+
+/// The earliest time candles can be computed for, 0001-01-01T00:00:00Z in ms. Like
+/// `MAX_TIME`, it leaves the calendar arithmetic room for the longest intervals.
+pub const MIN_TIME: i64 = -62_135_596_800_000;
+/// The latest time candles can be computed for, 9999-12-31T23:59:59.999Z in ms.
+pub const MAX_TIME: i64 = 253_402_300_799_999;
+
+/// The span of a candle, named like `5m`, `3h`, `1d` or `1mo`. Stored as its
+/// `Display` name in the `interval` columns.
+///
+/// Days, weeks and months follow the calendar of a timezone, UTC unless a `*_in`
+/// method is given another, and weeks start on Monday. Like binance, multi-day and
+/// multi-week spans count from the unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Interval {
+  Seconds(u32),
+  Minutes(u32),
+  Hours(u32),
+  Days(u32),
+  Weeks(u32),
+  Months(u32),
+}
+
+impl Interval {
+  /// Every interval binance publishes, finest first.
+  pub const BINANCE: [Interval; 16] = [
+    Interval::Seconds(1),
+    Interval::Minutes(1),
+    Interval::Minutes(3),
+    Interval::Minutes(5),
+    Interval::Minutes(15),
+    Interval::Minutes(30),
+    Interval::Hours(1),
+    Interval::Hours(2),
+    Interval::Hours(4),
+    Interval::Hours(6),
+    Interval::Hours(8),
+    Interval::Hours(12),
+    Interval::Days(1),
+    Interval::Days(3),
+    Interval::Weeks(1),
+    Interval::Months(1),
+  ];
+
+  /// The length of seconds, minutes and hours in ms, which UTC candles always have.
+  /// Around DST transitions their candles in other timezones can be shorter or longer.
+  fn fixed_millis(&self) -> Option<i64> {
+    match *self {
+      Interval::Seconds(n) => Some(n as i64 * SECOND),
+      Interval::Minutes(n) => Some(n as i64 * MINUTE),
+      Interval::Hours(n) => Some(n as i64 * HOUR),
+      Interval::Days(_) | Interval::Weeks(_) | Interval::Months(_) => None,
+    }
+  }
+
+  /// The `open_time` of the UTC candle holding `time`, in milliseconds.
+  pub fn open_time(&self, time: i64) -> i64 {
+    self.open_time_in(time, &Utc)
+  }
+
+  /// The `open_time` of the candle holding `time` in `tz`. Seconds, minutes and
+  /// hours open when the wall clock shows a multiple of their span, like days open
+  /// at midnight, and when a DST transition lands on or skips over one. Times
+  /// outside `MIN_TIME..=MAX_TIME` are clamped into it.
+  pub fn open_time_in<Tz: TimeZone>(&self, time: i64, tz: &Tz) -> i64 {
+    let time = time.clamp(MIN_TIME, MAX_TIME);
+    let date = utc(time).with_timezone(tz).date_naive();
+    match *self {
+      Interval::Seconds(_) | Interval::Minutes(_) | Interval::Hours(_) => {
+        wall_clock_open_time(time, self.fixed_millis().unwrap_or_default(), tz)
+      }
+      Interval::Days(n) => {
+        let days = (date - epoch()).num_days();
+        start_of_day(
+          epoch() + TimeDelta::days(days - days.rem_euclid(n as i64)),
+          tz,
+        )
+      }
+      Interval::Weeks(n) => {
+        let days = (date - first_monday()).num_days();
+        let span = 7 * n as i64;
+        start_of_day(
+          first_monday() + TimeDelta::days(days - days.rem_euclid(span)),
+          tz,
+        )
+      }
+      Interval::Months(n) => {
+        let months = (date.year() - 1970) * 12 + date.month0() as i32;
+        let start = months - months.rem_euclid(n as i32);
+        start_of_day(add_months(epoch(), start), tz)
+      }
+    }
+  }
+
+  /// The `open_time` of the UTC candle after the one opened at `open_time`.
+  pub fn next_open_time(&self, open_time: i64) -> i64 {
+    self.next_open_time_in(open_time, &Utc)
+  }
+
+  pub fn next_open_time_in<Tz: TimeZone>(&self, open_time: i64, tz: &Tz) -> i64 {
+    let open_time = open_time.clamp(MIN_TIME, MAX_TIME);
+    let date = utc(open_time).with_timezone(tz).date_naive();
+    match *self {
+      Interval::Seconds(_) | Interval::Minutes(_) | Interval::Hours(_) => {
+        wall_clock_next_open_time(open_time, self.fixed_millis().unwrap_or_default(), tz)
+      }
+      Interval::Days(n) => start_of_day(date + TimeDelta::days(n as i64), tz),
+      Interval::Weeks(n) => start_of_day(date + TimeDelta::weeks(n as i64), tz),
+      Interval::Months(n) => start_of_day(add_months(date, n as i32), tz),
+    }
+  }
+
+  /// The `close_time` of the UTC candle opened at `open_time`, its last millisecond.
+  pub fn close_time(&self, open_time: i64) -> i64 {
+    self.next_open_time(open_time) - 1
+  }
+
+  pub fn close_time_in<Tz: TimeZone>(&self, open_time: i64, tz: &Tz) -> i64 {
+    self.next_open_time_in(open_time, tz) - 1
+  }
+
+  /// `(step, offset)` in ms such that UTC candles open at the offset plus a multiple
+  /// of the step. `None` for months, which have no fixed length.
+  pub fn alignment(&self) -> Option<(i64, i64)> {
+    let first_monday = (first_monday() - epoch()).num_milliseconds();
+    match *self {
+      Interval::Seconds(_) | Interval::Minutes(_) | Interval::Hours(_) => {
+        self.fixed_millis().map(|span| (span, 0))
+      }
+      Interval::Days(n) => Some((n as i64 * DAY, 0)),
+      Interval::Weeks(n) => Some((n as i64 * 7 * DAY, first_monday)),
+      Interval::Months(_) => None,
+    }
+  }
+
+  /// An upper bound on the length of a candle, DST transitions included.
+  pub fn max_millis(&self) -> i64 {
+    match *self {
+      Interval::Seconds(_) | Interval::Minutes(_) | Interval::Hours(_) => {
+        let span = self.fixed_millis().unwrap_or_default();
+        // Clocks go back by at most an hour, in steps of 30 minutes. The repeated
+        // time holds whole candles of spans dividing 30 minutes and stretches others.
+        match (30 * MINUTE) % span {
+          0 => span,
+          _ => span + HOUR,
+        }
+      }
+      Interval::Days(n) => n as i64 * DAY + HOUR,
+      Interval::Weeks(n) => n as i64 * 7 * DAY + HOUR,
+      Interval::Months(n) => n as i64 * 31 * DAY + HOUR,
+    }
+  }
+
+  /// Whether candles of this interval, aligned in UTC like binance's, add up
+  /// exactly to the candles of `target` in `tz`.
+  pub fn divides<Tz: TimeZone>(&self, target: &Interval, tz: &Tz) -> bool {
+    // Offsets in winter and summer, to account for DST.
+    let offsets = [(2024, 1), (2024, 7)].map(|(year, month)| {
+      let date = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+      let offset = tz.offset_from_utc_date(&date).fix().local_minus_utc();
+      offset as i64 * SECOND
+    });
+    let is_utc = offsets.iter().all(|offset| *offset == 0);
+
+    if let Some(span) = self.fixed_millis() {
+      let target_span = target.fixed_millis().unwrap_or(DAY);
+      return target_span % span == 0 && offsets.iter().all(|offset| offset % span == 0);
+    }
+    match (*self, *target) {
+      (Interval::Days(a), Interval::Days(b)) => is_utc && b % a == 0,
+      (Interval::Days(1), Interval::Weeks(_) | Interval::Months(_)) => is_utc,
+      (Interval::Weeks(a), Interval::Weeks(b)) => is_utc && b % a == 0,
+      (Interval::Months(a), Interval::Months(b)) => is_utc && b % a == 0,
+      _ => false,
+    }
+  }
+}
+
+/// One minute, so default `Candle`s have a valid interval.
+impl Default for Interval {
+  fn default() -> Self {
+    Interval::Minutes(1)
+  }
+}
+
+impl fmt::Display for Interval {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      Interval::Seconds(n) => write!(f, "{n}s"),
+      Interval::Minutes(n) => write!(f, "{n}m"),
+      Interval::Hours(n) => write!(f, "{n}h"),
+      Interval::Days(n) => write!(f, "{n}d"),
+      Interval::Weeks(n) => write!(f, "{n}w"),
+      Interval::Months(n) => write!(f, "{n}mo"),
+    }
+  }
+}
+
+impl FromStr for Interval {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(digits);
+    let count: u32 = count
+      .parse()
+      .ok()
+      .filter(|count| *count > 0)
+      .ok_or_else(|| anyhow!("invalid interval {s:?}"))?;
+
+    let (interval, max): (fn(u32) -> Interval, u32) = match unit {
+      "s" => (Interval::Seconds, MAX_DAYS * 24 * 60 * 60),
+      "m" => (Interval::Minutes, MAX_DAYS * 24 * 60),
+      "h" => (Interval::Hours, MAX_DAYS * 24),
+      "d" => (Interval::Days, MAX_DAYS),
+      "w" => (Interval::Weeks, MAX_DAYS / 7),
+      "mo" => (Interval::Months, MAX_YEARS * 12),
+      // Binance's API calls the month `1M`, its archives `1mo`. Only the latter is
+      // accepted so that `M` and `m` can't be mixed up.
+      "M" => bail!("invalid interval {s:?}, months are written {count}mo"),
+      _ => bail!("invalid interval {s:?}, expected a number followed by s, m, h, d, w or mo"),
+    };
+    if count > max {
+      bail!("invalid interval {s:?}, intervals are at most {MAX_YEARS} years long");
+    }
+    Ok(interval(count))
+  }
+}
+
+impl Serialize for Interval {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for Interval {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+/// Exported as the string it serializes to.
+impl specta::Type for Interval {
+  fn inline(
+    opts: specta::DefOpts,
+    generics: &[specta::DataType],
+  ) -> Result<specta::DataType, specta::ExportError> {
+    <String as specta::Type>::inline(opts, generics)
+  }
+}
+
+impl sqlx::Type<Postgres> for Interval {
+  fn type_info() -> PgTypeInfo {
+    <&str as sqlx::Type<Postgres>>::type_info()
+  }
+
+  fn compatible(ty: &PgTypeInfo) -> bool {
+    <&str as sqlx::Type<Postgres>>::compatible(ty)
+  }
+}
+
+impl Encode<'_, Postgres> for Interval {
+  fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+    <String as Encode<Postgres>>::encode(self.to_string(), buf)
+  }
+}
+
+impl<'r> Decode<'r, Postgres> for Interval {
+  fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+    Ok(<&str as Decode<Postgres>>::decode(value)?.parse()?)
+  }
+}
+
+fn utc(time: i64) -> DateTime<Utc> {
+  DateTime::from_timestamp_millis(time.clamp(MIN_TIME, MAX_TIME)).unwrap_or_default()
+}
+
+fn epoch() -> NaiveDate {
+  NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()
+}
+
+/// `date` moved by `months`, which can be negative. Saturates at the ends of the
+/// calendar, far beyond `MIN_TIME..=MAX_TIME`.
+fn add_months(date: NaiveDate, months: i32) -> NaiveDate {
+  match u32::try_from(months) {
+    Ok(months) => date
+      .checked_add_months(Months::new(months))
+      .unwrap_or(NaiveDate::MAX),
+    Err(_) => date
+      .checked_sub_months(Months::new(months.unsigned_abs()))
+      .unwrap_or(NaiveDate::MIN),
+  }
+}
+
+/// Binance's weekly candles start on Mondays.
+fn first_monday() -> NaiveDate {
+  NaiveDate::from_ymd_opt(1970, 1, 5).unwrap()
+}
+
+/// The UTC offset of `tz` at `time`, in ms.
+fn offset_at<Tz: TimeZone>(time: i64, tz: &Tz) -> i64 {
+  utc(time).with_timezone(tz).offset().fix().local_minus_utc() as i64 * SECOND
+}
+
+/// The first time in `(after, until]` the offset of `tz` differs from the one at
+/// `after`, assuming it changes at most once in between.
+fn transition<Tz: TimeZone>(after: i64, until: i64, tz: &Tz) -> Option<i64> {
+  let offset = offset_at(after, tz);
+  if offset_at(until, tz) == offset {
+    return None;
+  }
+  let (mut same, mut changed) = (after, until);
+  while changed - same > 1 {
+    let mid = same + (changed - same) / 2;
+    if offset_at(mid, tz) == offset {
+      same = mid;
+    } else {
+      changed = mid;
+    }
+  }
+  Some(changed)
+}
+
+/// Whether candles of `span` open at the offset change at `time`: when the wall
+/// clock lands on a multiple of `span`, or skips over one when it moves forward.
+fn opens_at_transition<Tz: TimeZone>(time: i64, span: i64, tz: &Tz) -> bool {
+  let before = time + offset_at(time - 1, tz);
+  let after = time + offset_at(time, tz);
+  let first_multiple = before + (span - before.rem_euclid(span)) % span;
+  after.rem_euclid(span) == 0 || first_multiple <= after
+}
+
+/// The last time up to `time` where the wall clock of `tz` opened a candle of `span`.
+fn wall_clock_open_time<Tz: TimeZone>(time: i64, span: i64, tz: &Tz) -> i64 {
+  let open = time - (time + offset_at(time, tz)).rem_euclid(span);
+  match transition(open, time, tz) {
+    None => open,
+    Some(change) if opens_at_transition(change, span, tz) => change,
+    // The candle opened before the clocks changed.
+    Some(change) => wall_clock_open_time(change - 1, span, tz),
+  }
+}
+
+/// The first time after `open_time` where the wall clock of `tz` opens a candle of `span`.
+fn wall_clock_next_open_time<Tz: TimeZone>(open_time: i64, span: i64, tz: &Tz) -> i64 {
+  let next = open_time + span - (open_time + offset_at(open_time, tz)).rem_euclid(span);
+  match transition(open_time, next, tz) {
+    None => next,
+    Some(change) if opens_at_transition(change, span, tz) => change,
+    Some(change) => wall_clock_next_open_time(change, span, tz),
+  }
+}
+
+fn start_of_day<Tz: TimeZone>(date: NaiveDate, tz: &Tz) -> i64 {
+  let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+  // Some DST transitions skip midnight, the day then starts with the transition,
+  // which is at most a few hours later and on a quarter hour.
+  (0..=4 * 4)
+    .find_map(|quarter| {
+      let time = midnight + TimeDelta::minutes(15 * quarter);
+      tz.from_local_datetime(&time).earliest()
+    })
+    .unwrap_or_else(|| tz.from_utc_datetime(&midnight))
+    .timestamp_millis()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::FixedOffset;
+  use chrono_tz::{America::Sao_Paulo, Asia::Kolkata, Australia::Lord_Howe, Europe::Berlin};
+
+  fn at(time: &str) -> i64 {
+    DateTime::parse_from_rfc3339(time)
+      .unwrap()
+      .timestamp_millis()
+  }
+
+  fn parse(s: &str) -> Result<Interval, String> {
+    s.parse().map_err(|err: Error| err.to_string())
+  }
+
+  #[test]
+  fn round_trips_names() {
+    for interval in Interval::BINANCE {
+      assert_eq!(parse(&interval.to_string()), Ok(interval));
+    }
+    assert_eq!(parse("1mo"), Ok(Interval::Months(1)));
+    assert_eq!(parse("90m"), Ok(Interval::Minutes(90)));
+  }
+
+  #[test]
+  fn rejects_invalid_names() {
+    for s in ["", "m", "0m", "-1m", "1.5h", "1x", "1 m", "99999999999s"] {
+      assert!(parse(s).is_err(), "{s:?} parsed");
+    }
+    assert_eq!(
+      parse("1M"),
+      Err("invalid interval \"1M\", months are written 1mo".to_string())
+    );
+  }
+
+  #[test]
+  fn rejects_intervals_longer_than_a_century() {
+    assert_eq!(parse("1200mo"), Ok(Interval::Months(1200)));
+    assert_eq!(parse("36600d"), Ok(Interval::Days(36600)));
+    for s in [
+      "1201mo",
+      "36601d",
+      "5229w",
+      "878401h",
+      "4294967295mo",
+      "4294967295d",
+    ] {
+      assert_eq!(
+        parse(s),
+        Err(format!(
+          "invalid interval {s:?}, intervals are at most 100 years long"
+        ))
+      );
+    }
+
+    // The longest intervals stay clear of overflows and chrono's panics.
+    let now = at("2024-01-01T00:00:00Z");
+    for s in ["1200mo", "36600d", "5228w", "878400h", "3162240000s"] {
+      let interval: Interval = s.parse().unwrap();
+      assert!(interval.max_millis() > 0);
+      assert!(interval.open_time_in(now, &Berlin) <= now);
+      assert!(interval.next_open_time_in(now, &Berlin) > now);
+    }
+  }
+
+  #[test]
+  fn opens_fixed_intervals_on_multiples() {
+    let time = at("2024-01-01T03:17:42.5Z");
+    assert_eq!(
+      Interval::Seconds(1).open_time(time),
+      at("2024-01-01T03:17:42Z")
+    );
+    assert_eq!(
+      Interval::Minutes(15).open_time(time),
+      at("2024-01-01T03:15:00Z")
+    );
+    assert_eq!(
+      Interval::Hours(4).open_time(time),
+      at("2024-01-01T00:00:00Z")
+    );
+    assert_eq!(
+      Interval::Hours(4).close_time(at("2024-01-01T00:00:00Z")),
+      at("2024-01-01T03:59:59.999Z")
+    );
+  }
+
+  #[test]
+  fn aligns_fixed_intervals_to_the_timezone() {
+    let ist = FixedOffset::east_opt(5 * 3600 + 1800).unwrap();
+    let time = at("2024-01-01T03:00:00Z");
+    // 08:30 in India, the 4h candle opened at 08:00 there.
+    assert_eq!(
+      Interval::Hours(4).open_time_in(time, &ist),
+      at("2024-01-01T02:30:00Z")
+    );
+    assert_eq!(
+      Interval::Hours(4).open_time_in(time, &Kolkata),
+      at("2024-01-01T02:30:00Z")
+    );
+    assert_eq!(
+      Interval::Minutes(30).open_time_in(time, &Kolkata),
+      at("2024-01-01T03:00:00Z")
+    );
+  }
+
+  #[test]
+  fn counts_days_from_the_epoch() {
+    let time = at("2024-01-01T12:00:00Z");
+    assert_eq!(
+      Interval::Days(1).open_time(time),
+      at("2024-01-01T00:00:00Z")
+    );
+    assert_eq!(
+      Interval::Days(3).open_time(time),
+      at("2023-12-31T00:00:00Z")
+    );
+    assert_eq!(
+      Interval::Days(3).next_open_time(at("2023-12-31T00:00:00Z")),
+      at("2024-01-03T00:00:00Z")
+    );
+  }
+
+  #[test]
+  fn starts_weeks_on_mondays() {
+    // A wednesday.
+    let time = at("2024-01-03T12:00:00Z");
+    assert_eq!(
+      Interval::Weeks(1).open_time(time),
+      at("2024-01-01T00:00:00Z")
+    );
+    assert_eq!(
+      Interval::Weeks(1).close_time(at("2024-01-01T00:00:00Z")),
+      at("2024-01-07T23:59:59.999Z")
+    );
+    assert_eq!(
+      Interval::Weeks(2).open_time(time),
+      at("2023-12-25T00:00:00Z")
+    );
+    assert_eq!(
+      Interval::Weeks(1).open_time_in(time, &Berlin),
+      at("2023-12-31T23:00:00Z")
+    );
+  }
+
+  #[test]
+  fn follows_the_calendar_for_months() {
+    let time = at("2024-02-15T12:00:00Z");
+    assert_eq!(
+      Interval::Months(1).open_time(time),
+      at("2024-02-01T00:00:00Z")
+    );
+    assert_eq!(
+      Interval::Months(1).close_time(at("2024-02-01T00:00:00Z")),
+      at("2024-02-29T23:59:59.999Z")
+    );
+    assert_eq!(
+      Interval::Months(3).open_time(at("2024-05-10T00:00:00Z")),
+      at("2024-04-01T00:00:00Z")
+    );
+    assert_eq!(
+      Interval::Months(1).open_time_in(time, &Berlin),
+      at("2024-01-31T23:00:00Z")
+    );
+    // Months before 1970 count back from the epoch.
+    assert_eq!(
+      Interval::Months(1).open_time(-1),
+      at("1969-12-01T00:00:00Z")
+    );
+    assert_eq!(
+      Interval::Months(12).open_time(at("1969-06-15T00:00:00Z")),
+      at("1969-01-01T00:00:00Z")
+    );
+    assert_eq!(
+      Interval::Months(1).close_time(at("1969-12-01T00:00:00Z")),
+      -1
+    );
+  }
+
+  #[test]
+  fn clamps_times_to_the_calendar() {
+    for interval in [
+      Interval::Hours(1),
+      Interval::Days(1),
+      Interval::Months(1200),
+    ] {
+      assert_eq!(interval.open_time(i64::MIN), interval.open_time(MIN_TIME));
+      assert_eq!(interval.open_time(i64::MAX), interval.open_time(MAX_TIME));
+      assert!(interval.close_time(interval.open_time(MAX_TIME)) >= MAX_TIME);
+      assert!(interval.open_time_in(MIN_TIME, &Sao_Paulo) <= MIN_TIME);
+    }
+  }
+
+  #[test]
+  fn follows_dst_transitions() {
+    // Berlin skips from 02:00 to 03:00 on 2024-03-31, a 23h day.
+    let day = Interval::Days(1);
+    let open = day.open_time_in(at("2024-03-31T12:00:00Z"), &Berlin);
+    assert_eq!(open, at("2024-03-30T23:00:00Z"));
+    assert_eq!(
+      day.close_time_in(open, &Berlin),
+      at("2024-03-31T21:59:59.999Z")
+    );
+
+    // And back on 2024-10-27, a 25h day.
+    let open = day.open_time_in(at("2024-10-27T12:00:00Z"), &Berlin);
+    assert_eq!(open, at("2024-10-26T22:00:00Z"));
+    assert_eq!(
+      day.next_open_time_in(open, &Berlin),
+      at("2024-10-27T23:00:00Z")
+    );
+    assert!(day.next_open_time_in(open, &Berlin) - open <= day.max_millis());
+
+    // Hours follow the wall clock: 3h candles open at 00:00 and 03:00 in Berlin,
+    // the 2h and 4h candles around the changes.
+    let hours = Interval::Hours(3);
+    let candle = |time| {
+      let open = hours.open_time_in(at(time), &Berlin);
+      (open, hours.close_time_in(open, &Berlin))
+    };
+    let spring = (at("2024-03-30T23:00:00Z"), at("2024-03-31T00:59:59.999Z"));
+    assert_eq!(candle("2024-03-30T23:00:00Z"), spring);
+    assert_eq!(candle("2024-03-31T00:30:00Z"), spring);
+    let after = (at("2024-03-31T01:00:00Z"), at("2024-03-31T03:59:59.999Z"));
+    assert_eq!(candle("2024-03-31T01:00:00Z"), after);
+    assert_eq!(candle("2024-03-31T01:30:00Z"), after);
+
+    let autumn = (at("2024-10-26T22:00:00Z"), at("2024-10-27T01:59:59.999Z"));
+    assert_eq!(candle("2024-10-26T22:00:00Z"), autumn);
+    assert_eq!(candle("2024-10-27T00:30:00Z"), autumn);
+    assert_eq!(candle("2024-10-27T01:30:00Z"), autumn);
+    assert_eq!(
+      candle("2024-10-27T02:30:00Z"),
+      (at("2024-10-27T02:00:00Z"), at("2024-10-27T04:59:59.999Z"))
+    );
+
+    // The repeated hour is a candle of its own.
+    let hour = Interval::Hours(1);
+    let open = hour.open_time_in(at("2024-10-27T01:30:00Z"), &Berlin);
+    assert_eq!(open, at("2024-10-27T01:00:00Z"));
+    assert_eq!(
+      hour.close_time_in(open, &Berlin),
+      at("2024-10-27T01:59:59.999Z")
+    );
+    let open = hour.open_time_in(at("2024-10-27T00:30:00Z"), &Berlin);
+    assert_eq!(open, at("2024-10-27T00:00:00Z"));
+    assert_eq!(
+      hour.close_time_in(open, &Berlin),
+      at("2024-10-27T00:59:59.999Z")
+    );
+
+    // Sao Paulo skipped midnight on 2018-11-04, the day started at 01:00 there.
+    let open = day.open_time_in(at("2018-11-04T12:00:00Z"), &Sao_Paulo);
+    assert_eq!(open, at("2018-11-04T01:00:00-02:00"));
+    assert_eq!(
+      day.next_open_time_in(at("2018-11-03T12:00:00Z"), &Sao_Paulo),
+      open
+    );
+  }
+
+  #[test]
+  fn aligns_to_the_epoch_and_mondays() {
+    assert_eq!(Interval::Hours(4).alignment(), Some((4 * HOUR, 0)));
+    assert_eq!(Interval::Days(3).alignment(), Some((3 * DAY, 0)));
+    assert_eq!(Interval::Weeks(1).alignment(), Some((7 * DAY, 4 * DAY)));
+    assert_eq!(Interval::Months(1).alignment(), None);
+
+    let (step, offset) = Interval::Weeks(1).alignment().unwrap();
+    assert_eq!((at("2024-01-01T00:00:00Z") - offset) % step, 0);
+  }
+
+  #[test]
+  fn divides_only_whole_candles() {
+    let day = Interval::Days(1);
+    assert!(Interval::Hours(1).divides(&day, &Utc));
+    assert!(Interval::Hours(1).divides(&day, &Berlin));
+    assert!(!Interval::Hours(5).divides(&day, &Utc));
+    assert!(!Interval::Hours(1).divides(&day, &Kolkata));
+    assert!(Interval::Minutes(30).divides(&day, &Kolkata));
+    assert!(Interval::Hours(1).divides(&Interval::Hours(4), &Utc));
+
+    assert!(day.divides(&Interval::Days(3), &Utc));
+    assert!(!Interval::Days(2).divides(&Interval::Days(3), &Utc));
+    assert!(day.divides(&Interval::Weeks(1), &Utc));
+    assert!(day.divides(&Interval::Months(1), &Utc));
+    assert!(!day.divides(&Interval::Weeks(1), &Berlin));
+    assert!(!Interval::Weeks(1).divides(&Interval::Months(1), &Utc));
+    assert!(Interval::Months(1).divides(&Interval::Months(3), &Utc));
+  }
+
+  /// Walks the candles holding every 5 minutes from `from` to `to`, checking that
+  /// they hold the time, follow each other without overlaps and stay within
+  /// `max_millis`.
+  fn assert_contiguous<Tz: TimeZone>(interval: Interval, tz: &Tz, from: &str, to: &str) {
+    let mut previous_close = None;
+    let mut time = at(from);
+    while time <= at(to) {
+      let open = interval.open_time_in(time, tz);
+      let close = interval.close_time_in(open, tz);
+      assert!(
+        open <= time && time <= close,
+        "{interval} at {time}: {open}..{close}"
+      );
+      assert!(
+        close - open < interval.max_millis(),
+        "{interval} at {time} is too long"
+      );
+      assert_eq!(
+        interval.open_time_in(open, tz),
+        open,
+        "{interval} at {time}"
+      );
+      assert_eq!(
+        interval.open_time_in(close, tz),
+        open,
+        "{interval} at {time}"
+      );
+      if let Some(previous_close) = previous_close.filter(|c| *c < open) {
+        assert_eq!(open, previous_close + 1, "{interval} at {time} skips time");
+      }
+      previous_close = Some(close);
+      time += 5 * MINUTE;
+    }
+  }
+
+  #[test]
+  fn keeps_candles_contiguous_across_dst() {
+    let intervals = [
+      Interval::Minutes(5),
+      Interval::Minutes(15),
+      Interval::Minutes(45),
+      Interval::Minutes(90),
+      Interval::Hours(1),
+      Interval::Hours(2),
+      Interval::Hours(3),
+      Interval::Hours(4),
+      Interval::Hours(12),
+    ];
+    for interval in intervals {
+      assert_contiguous(
+        interval,
+        &Berlin,
+        "2024-03-30T12:00:00Z",
+        "2024-04-01T00:00:00Z",
+      );
+      assert_contiguous(
+        interval,
+        &Berlin,
+        "2024-10-26T12:00:00Z",
+        "2024-10-28T00:00:00Z",
+      );
+      assert_contiguous(
+        interval,
+        &Sao_Paulo,
+        "2018-11-03T12:00:00Z",
+        "2018-11-05T00:00:00Z",
+      );
+      // Lord Howe moves its clocks by 30 minutes.
+      assert_contiguous(
+        interval,
+        &Lord_Howe,
+        "2024-04-06T00:00:00Z",
+        "2024-04-08T00:00:00Z",
+      );
+      assert_contiguous(
+        interval,
+        &Lord_Howe,
+        "2024-10-05T00:00:00Z",
+        "2024-10-07T00:00:00Z",
+      );
+    }
+  }
+}
@@ -1,7 +1,21 @@
+mod agg_trade;
+mod bulk;
 mod candle;
+mod candle_audit;
+mod download_cursor;
+mod ingested_file;
+mod interval;
+mod market;
 mod symbol;
 mod user;
 
+pub use agg_trade::*;
+pub use bulk::month_millis;
 pub use candle::*;
+pub use candle_audit::*;
+pub use download_cursor::*;
+pub use ingested_file::*;
+pub use interval::*;
+pub use market::*;
 pub use symbol::*;
 pub use user::*;
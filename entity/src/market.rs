@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+  encode::IsNull,
+  error::BoxDynError,
+  postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef},
+  Decode, Encode, Postgres,
+};
+use std::{fmt, str::FromStr};
+
+/// A binance market. Stored as its `as_str` name in the `market` columns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, specta::Type)]
+#[serde(rename_all = "lowercase")]
+pub enum Market {
+  #[default]
+  Spot,
+  /// USDⓈ-M futures, margined in USDT or USDC
+  #[serde(rename = "um")]
+  UsdM,
+  /// COIN-M futures, margined in the base asset
+  #[serde(rename = "cm")]
+  CoinM,
+}
+
+impl Market {
+  pub const ALL: [Market; 3] = [Market::Spot, Market::UsdM, Market::CoinM];
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Market::Spot => "spot",
+      Market::UsdM => "um",
+      Market::CoinM => "cm",
+    }
+  }
+
+  /// The market's tree on data.binance.vision, e.g. `futures/um`.
+  pub fn archive_root(self) -> &'static str {
+    match self {
+      Market::Spot => "spot",
+      Market::UsdM => "futures/um",
+      Market::CoinM => "futures/cm",
+    }
+  }
+
+  /// The `klines` path on the market's API host.
+  pub fn klines_path(self) -> &'static str {
+    match self {
+      Market::Spot => "api/v3/klines",
+      Market::UsdM => "fapi/v1/klines",
+      Market::CoinM => "dapi/v1/klines",
+    }
+  }
+
+  /// The `exchangeInfo` path on the market's API host.
+  pub fn exchange_info_path(self) -> &'static str {
+    match self {
+      Market::Spot => "api/v3/exchangeInfo",
+      Market::UsdM => "fapi/v1/exchangeInfo",
+      Market::CoinM => "dapi/v1/exchangeInfo",
+    }
+  }
+}
+
+impl fmt::Display for Market {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl FromStr for Market {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Market::ALL
+      .into_iter()
+      .find(|m| m.as_str() == s)
+      .ok_or_else(|| anyhow!("unknown market {s:?}, expected spot, um or cm"))
+  }
+}
+
+impl sqlx::Type<Postgres> for Market {
+  fn type_info() -> PgTypeInfo {
+    <&str as sqlx::Type<Postgres>>::type_info()
+  }
+
+  fn compatible(ty: &PgTypeInfo) -> bool {
+    <&str as sqlx::Type<Postgres>>::compatible(ty)
+  }
+}
+
+impl Encode<'_, Postgres> for Market {
+  fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+    <&str as Encode<Postgres>>::encode(self.as_str(), buf)
+  }
+}
+
+impl<'r> Decode<'r, Postgres> for Market {
+  fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+    Ok(<&str as Decode<Postgres>>::decode(value)?.parse()?)
+  }
+}
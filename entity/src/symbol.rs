@@ -1,26 +1,105 @@
-use anyhow::Result;
-use serde::Deserialize;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as, PgConnection, PgPool};
-use tracing::{info, warn};
+use std::collections::HashMap;
+use tracing::info;
 
-#[derive(Deserialize)]
+use crate::Market;
+
+#[derive(Serialize, specta::Type)]
 pub struct Symbol {
+  pub market: Market,
   pub symbol: String,
-  // possible vaules: TRADING, BREAK
+  // possible vaules: TRADING, BREAK, or DELISTED once binance stops listing it.
+  // Futures also go through PENDING_TRADING, SETTLING, etc.
   pub status: String,
-  #[serde(rename = "baseAsset")]
   pub base_asset: String,
-  #[serde(rename = "quoteAsset")]
   pub quote_asset: String,
+  pub first_seen_at: DateTime<Utc>,
+  pub last_seen_at: DateTime<Utc>,
+  pub delisted_at: Option<DateTime<Utc>>,
+  /// Prices must be a multiple of this (`PRICE_FILTER`)
+  pub tick_size: Option<Decimal>,
+  /// Quantities must be a multiple of this (`LOT_SIZE`)
+  pub step_size: Option<Decimal>,
+  /// Smallest accepted `price * quantity` (`NOTIONAL` or `MIN_NOTIONAL`)
+  pub min_notional: Option<Decimal>,
+  pub base_asset_precision: i32,
+  pub quote_asset_precision: i32,
+  pub order_types: Vec<String>,
+  pub permissions: Vec<String>,
+}
+
+/// What a `Symbol::sync_all` changed.
+#[derive(Default)]
+pub struct SyncSummary {
+  pub added: Vec<String>,
+  /// `(symbol, old status, new status)`
+  pub changed: Vec<(String, String, String)>,
+  pub delisted: Vec<String>,
+}
+
+/// Optional constraints for `Symbol::search`, unset fields match everything.
+#[derive(Deserialize, Default)]
+pub struct SymbolFilter {
+  pub market: Option<Market>,
+  pub status: Option<String>,
+  pub base_asset: Option<String>,
+  pub quote_asset: Option<String>,
+  /// Matches symbols starting with this value
+  pub prefix: Option<String>,
 }
 
 impl Symbol {
-  pub async fn fetch_all(pool: &PgPool) -> Result<Vec<Self>> {
+  /// Rounds a price down to a multiple of the tick size.
+  pub fn round_price(&self, price: Decimal) -> Decimal {
+    round_to_step(price, self.tick_size)
+  }
+
+  /// Rounds a quantity down to a multiple of the step size.
+  pub fn round_quantity(&self, quantity: Decimal) -> Decimal {
+    round_to_step(quantity, self.step_size)
+  }
+
+  /// Whether binance accepts an order of this size.
+  pub fn meets_min_notional(&self, price: Decimal, quantity: Decimal) -> bool {
+    self
+      .min_notional
+      .is_none_or(|min_notional| price * quantity >= min_notional)
+  }
+
+  pub async fn fetch_all(pool: &PgPool, market: Market) -> Result<Vec<Self>> {
+    let filter = SymbolFilter {
+      market: Some(market),
+      status: Some("TRADING".to_string()),
+      ..Default::default()
+    };
+    Self::search(pool, &filter).await
+  }
+
+  pub async fn search(pool: &PgPool, filter: &SymbolFilter) -> Result<Vec<Self>> {
     let symbols = query_as!(
       Self,
       r#"--sql
-SELECT * FROM symbols s WHERE s.status = 'TRADING';
-      "#
+SELECT
+  s.market AS "market: Market", s.symbol, s.status, s.base_asset, s.quote_asset,
+  s.first_seen_at, s.last_seen_at, s.delisted_at, s.tick_size, s.step_size, s.min_notional,
+  s.base_asset_precision, s.quote_asset_precision, s.order_types, s.permissions
+FROM symbols s
+WHERE ( $1::TEXT IS NULL OR s.status = $1 )
+  AND ( $2::TEXT IS NULL OR s.base_asset = $2 )
+  AND ( $3::TEXT IS NULL OR s.quote_asset = $3 )
+  AND ( $4::TEXT IS NULL OR starts_with(s.symbol, $4) )
+  AND ( $5::TEXT IS NULL OR s.market = $5 )
+ORDER BY s.market, s.symbol;
+      "#,
+      filter.status,
+      filter.base_asset,
+      filter.quote_asset,
+      filter.prefix,
+      filter.market.map(Market::as_str)
     )
     .fetch_all(pool)
     .await?;
@@ -28,64 +107,317 @@ SELECT * FROM symbols s WHERE s.status = 'TRADING';
     Ok(symbols)
   }
 
-  pub async fn fetch_btc_usdt_pairs(pool: &PgPool) -> Result<Vec<Self>> {
-    let symbols = query_as!(
+  pub async fn find(pool: &PgPool, market: Market, symbol: &str) -> Result<Option<Self>> {
+    let symbol = query_as!(
       Self,
       r#"--sql
-SELECT * FROM symbols s WHERE s.status = 'TRADING' AND s.quote_asset = 'BTC' OR s.quote_asset = 'USDT';
-      "#
+SELECT
+  s.market AS "market: Market", s.symbol, s.status, s.base_asset, s.quote_asset,
+  s.first_seen_at, s.last_seen_at, s.delisted_at, s.tick_size, s.step_size, s.min_notional,
+  s.base_asset_precision, s.quote_asset_precision, s.order_types, s.permissions
+FROM symbols s
+WHERE s.market = $1 AND s.symbol = $2;
+      "#,
+      market.as_str(),
+      symbol
     )
-    .fetch_all(pool)
+    .fetch_optional(pool)
     .await?;
 
-    Ok(symbols)
+    Ok(symbol)
   }
 
-  pub async fn populate_all(pool: &mut PgConnection) -> Result<()> {
-    let resp = reqwest::get("https://api.binance.com/api/v3/exchangeInfo")
-      .await?
-      .text()
-      .await?;
-    let resp: ExchangeInfoResponse = serde_json::from_str(&resp).unwrap();
+  /// Upserts every symbol binance lists in a market and marks the ones it no longer
+  /// lists as delisted. `api_url` is the market's API host, it can also be a `file://`
+  /// url or a directory holding `Market::exchange_info_path`.
+  pub async fn sync_all(
+    pool: &mut PgConnection,
+    market: Market,
+    api_url: &str,
+  ) -> Result<SyncSummary> {
+    let symbols = fetch_exchange_info(market, api_url).await?;
+
+    let existing: HashMap<String, String> = query!(
+      r#"--sql
+SELECT symbol, status FROM symbols WHERE market = $1;
+      "#,
+      market.as_str()
+    )
+    .fetch_all(&mut *pool)
+    .await?
+    .into_iter()
+    .map(|r| (r.symbol, r.status))
+    .collect();
+
+    let mut summary = SyncSummary::default();
+    for symbol in &symbols {
+      let (tick_size, step_size, min_notional) = symbol.sizes();
+      let permissions = symbol.all_permissions();
+      let quote_asset_precision = symbol
+        .quote_asset_precision
+        .or(symbol.quote_precision)
+        .with_context(|| format!("{} has no quote precision", symbol.symbol))?;
 
-    for symbol in resp.symbols {
-      let result = query!(
+      query!(
         r#"--sql
 INSERT INTO symbols
-( symbol, status, base_asset, quote_asset )
-VALUES ( $1, $2, $3, $4 );
+( market, symbol, status, base_asset, quote_asset, tick_size, step_size, min_notional,
+  base_asset_precision, quote_asset_precision, order_types, permissions )
+VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12 )
+ON CONFLICT ( market, symbol ) DO UPDATE
+SET status = EXCLUDED.status,
+  base_asset = EXCLUDED.base_asset,
+  quote_asset = EXCLUDED.quote_asset,
+  tick_size = EXCLUDED.tick_size,
+  step_size = EXCLUDED.step_size,
+  min_notional = EXCLUDED.min_notional,
+  base_asset_precision = EXCLUDED.base_asset_precision,
+  quote_asset_precision = EXCLUDED.quote_asset_precision,
+  order_types = EXCLUDED.order_types,
+  permissions = EXCLUDED.permissions,
+  last_seen_at = NOW(),
+  delisted_at = NULL;
           "#,
+        market.as_str(),
         symbol.symbol,
         symbol.status,
         symbol.base_asset,
-        symbol.quote_asset
+        symbol.quote_asset,
+        tick_size,
+        step_size,
+        min_notional,
+        symbol.base_asset_precision,
+        quote_asset_precision,
+        &symbol.order_types,
+        &permissions
       )
       .execute(&mut *pool)
-      .await;
-
-      match result {
-        Err(err) => {
-          if let sqlx::Error::Database(db_err) = &err {
-            if let Some(cons) = db_err.constraint() {
-              if cons == "symbols_pkey" {
-                warn!("{} already exists in database.", symbol.symbol);
-                continue;
-              }
-            }
-          }
-          Err(err)?;
+      .await?;
+
+      match existing.get(&symbol.symbol) {
+        None => {
+          info!("Added {market} symbol {}", symbol.symbol);
+          summary.added.push(symbol.symbol.clone());
         }
-        _ => {
-          info!("Saved symbol {}", symbol.symbol);
+        Some(status) if *status != symbol.status => {
+          info!(
+            "{market} {} changed from {status} to {}",
+            symbol.symbol, symbol.status
+          );
+          summary
+            .changed
+            .push((symbol.symbol.clone(), status.clone(), symbol.status.clone()));
         }
+        _ => {}
       }
     }
 
-    Ok(())
+    let listed: Vec<String> = symbols.into_iter().map(|s| s.symbol).collect();
+    summary.delisted = query!(
+      r#"--sql
+UPDATE symbols
+SET status = 'DELISTED', delisted_at = NOW()
+WHERE market = $1 AND delisted_at IS NULL AND NOT ( symbol = ANY($2) )
+RETURNING symbol;
+      "#,
+      market.as_str(),
+      &listed
+    )
+    .fetch_all(&mut *pool)
+    .await?
+    .into_iter()
+    .map(|r| r.symbol)
+    .collect();
+
+    for symbol in &summary.delisted {
+      info!("Delisted {market} symbol {symbol}");
+    }
+
+    Ok(summary)
+  }
+}
+
+/// Reads the symbols of a market's `exchangeInfo` from `api_url`, like `sync_all`.
+async fn fetch_exchange_info(market: Market, api_url: &str) -> Result<Vec<ExchangeSymbol>> {
+  let url = format!(
+    "{}/{}",
+    api_url.trim_end_matches('/'),
+    market.exchange_info_path()
+  );
+  let resp = match url.strip_prefix("file://") {
+    Some(path) => tokio::fs::read_to_string(path).await?,
+    None if !url.starts_with("http://") && !url.starts_with("https://") => {
+      tokio::fs::read_to_string(&url).await?
+    }
+    None => reqwest::get(&url).await?.text().await?,
+  };
+  let resp: ExchangeInfoResponse = serde_json::from_str(&resp)?;
+  if resp.symbols.is_empty() {
+    bail!("{market} exchangeInfo listed no symbols, refusing to delist everything");
   }
+  Ok(resp.symbols)
+}
+
+fn round_to_step(value: Decimal, step: Option<Decimal>) -> Decimal {
+  match step {
+    Some(step) if !step.is_zero() => ((value / step).floor() * step).normalize(),
+    _ => value,
+  }
+}
+
+/// A symbol as listed by the spot or futures `exchangeInfo`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExchangeSymbol {
+  symbol: String,
+  #[serde(alias = "contractStatus")]
+  status: String,
+  base_asset: String,
+  quote_asset: String,
+  base_asset_precision: i32,
+  /// Spot only, futures only have `quotePrecision`
+  quote_asset_precision: Option<i32>,
+  quote_precision: Option<i32>,
+  /// COIN-M futures call it `OrderType`
+  #[serde(alias = "OrderType", default)]
+  order_types: Vec<String>,
+  #[serde(default)]
+  permissions: Vec<String>,
+  #[serde(default)]
+  permission_sets: Vec<PermissionSet>,
+  filters: Vec<ExchangeFilter>,
+}
+
+impl ExchangeSymbol {
+  /// The `tick_size`, `step_size` and `min_notional` from the filters.
+  fn sizes(&self) -> (Option<Decimal>, Option<Decimal>, Option<Decimal>) {
+    let (mut tick_size, mut step_size, mut min_notional) = (None, None, None);
+    for filter in &self.filters {
+      match filter {
+        ExchangeFilter::Price { tick_size: size } => tick_size = Some(*size),
+        ExchangeFilter::LotSize { step_size: size } => step_size = Some(*size),
+        ExchangeFilter::Notional { min_notional: min }
+        | ExchangeFilter::MinNotional { min_notional: min } => min_notional = Some(*min),
+        ExchangeFilter::Other => {}
+      }
+    }
+    (tick_size, step_size, min_notional)
+  }
+
+  /// `permissions` is being replaced by `permissionSets`, keep both.
+  fn all_permissions(&self) -> Vec<String> {
+    let mut permissions = self.permissions.clone();
+    for set in &self.permission_sets {
+      let set = match set {
+        PermissionSet::All(set) => set.as_slice(),
+        PermissionSet::One(permission) => std::slice::from_ref(permission),
+      };
+      for permission in set {
+        if !permissions.contains(permission) {
+          permissions.push(permission.clone());
+        }
+      }
+    }
+    permissions
+  }
+}
+
+/// Spot lists sets of permissions, USDⓈ-M futures single ones.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PermissionSet {
+  All(Vec<String>),
+  One(String),
+}
+
+/// The `exchangeInfo` filters needed to round orders, the rest are ignored.
+#[derive(Deserialize)]
+#[serde(tag = "filterType")]
+enum ExchangeFilter {
+  #[serde(rename = "PRICE_FILTER", rename_all = "camelCase")]
+  Price { tick_size: Decimal },
+  #[serde(rename = "LOT_SIZE", rename_all = "camelCase")]
+  LotSize { step_size: Decimal },
+  #[serde(rename = "NOTIONAL", rename_all = "camelCase")]
+  Notional { min_notional: Decimal },
+  /// USDⓈ-M futures call the value `notional`
+  #[serde(rename = "MIN_NOTIONAL", rename_all = "camelCase")]
+  MinNotional {
+    #[serde(alias = "notional")]
+    min_notional: Decimal,
+  },
+  #[serde(other)]
+  Other,
 }
 
 #[derive(Deserialize)]
 struct ExchangeInfoResponse {
-  symbols: Vec<Symbol>,
+  symbols: Vec<ExchangeSymbol>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rust_decimal::prelude::FromStr;
+
+  /// A mirror of the three `exchangeInfo` endpoints, trimmed to a few symbols.
+  const MIRROR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/fixtures/exchange_info");
+
+  fn decimal(s: &str) -> Option<Decimal> {
+    Some(Decimal::from_str(s).unwrap())
+  }
+
+  #[tokio::test]
+  async fn reads_spot_exchange_info() {
+    let symbols = fetch_exchange_info(Market::Spot, MIRROR).await.unwrap();
+    let names: Vec<_> = symbols.iter().map(|s| s.symbol.as_str()).collect();
+    assert_eq!(names, ["BTCUSDT", "ETHBTC"]);
+
+    let btc = &symbols[0];
+    assert_eq!(btc.status, "TRADING");
+    assert_eq!(btc.quote_asset_precision, Some(8));
+    assert!(btc.order_types.contains(&"LIMIT_MAKER".to_string()));
+    assert_eq!(
+      btc.sizes(),
+      (decimal("0.01"), decimal("0.00001"), decimal("5"))
+    );
+    assert_eq!(
+      btc.all_permissions(),
+      ["SPOT", "MARGIN", "TRD_GRP_004", "TRD_GRP_005"]
+    );
+    assert_eq!(symbols[1].status, "BREAK");
+  }
+
+  #[tokio::test]
+  async fn reads_usdm_exchange_info() {
+    let symbols = fetch_exchange_info(Market::UsdM, &format!("file://{MIRROR}"))
+      .await
+      .unwrap();
+    let btc = &symbols[0];
+    assert_eq!(btc.symbol, "BTCUSDT");
+    assert_eq!(btc.quote_asset_precision, None);
+    assert_eq!(btc.quote_precision, Some(8));
+    assert!(btc
+      .order_types
+      .contains(&"TRAILING_STOP_MARKET".to_string()));
+    // The notional is `notional` under `MIN_NOTIONAL`.
+    assert_eq!(
+      btc.sizes(),
+      (decimal("0.10"), decimal("0.001"), decimal("100"))
+    );
+    assert_eq!(btc.all_permissions(), ["GRID", "COPY"]);
+    assert_eq!(symbols[1].status, "SETTLING");
+  }
+
+  #[tokio::test]
+  async fn reads_coinm_exchange_info() {
+    let symbols = fetch_exchange_info(Market::CoinM, MIRROR).await.unwrap();
+    let btc = &symbols[0];
+    assert_eq!(btc.symbol, "BTCUSD_PERP");
+    assert_eq!(btc.status, "TRADING");
+    assert_eq!(btc.order_types.len(), 7);
+    assert_eq!(btc.sizes(), (decimal("0.1"), decimal("1"), None));
+    assert!(btc.all_permissions().is_empty());
+    assert_eq!(symbols[1].symbol, "BTCUSD_250328");
+  }
 }
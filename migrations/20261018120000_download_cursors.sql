@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS download_cursors (
+  symbol          TEXT NOT NULL,
+  interval        TEXT NOT NULL,
+  fetched_through DATE NOT NULL,
+  updated_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY(symbol, interval)
+);
@@ -0,0 +1,8 @@
+ALTER TABLE symbols
+  ADD COLUMN IF NOT EXISTS tick_size             NUMERIC,
+  ADD COLUMN IF NOT EXISTS step_size             NUMERIC,
+  ADD COLUMN IF NOT EXISTS min_notional          NUMERIC,
+  ADD COLUMN IF NOT EXISTS base_asset_precision  INTEGER NOT NULL DEFAULT 8,
+  ADD COLUMN IF NOT EXISTS quote_asset_precision INTEGER NOT NULL DEFAULT 8,
+  ADD COLUMN IF NOT EXISTS order_types           TEXT[] NOT NULL DEFAULT '{}',
+  ADD COLUMN IF NOT EXISTS permissions           TEXT[] NOT NULL DEFAULT '{}';
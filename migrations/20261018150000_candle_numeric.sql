@@ -0,0 +1,10 @@
+-- REAL values were already rounded when they were loaded. Those candles have no
+-- close_time, which came later, and reloading the history rewrites candles with a
+-- NULL close_time whole, so a plain `history load` restores their exact prices.
+ALTER TABLE candles
+  ALTER COLUMN open         TYPE NUMERIC USING open::NUMERIC,
+  ALTER COLUMN close        TYPE NUMERIC USING close::NUMERIC,
+  ALTER COLUMN high         TYPE NUMERIC USING high::NUMERIC,
+  ALTER COLUMN low          TYPE NUMERIC USING low::NUMERIC,
+  ALTER COLUMN volume       TYPE NUMERIC USING volume::NUMERIC,
+  ALTER COLUMN taker_volume TYPE NUMERIC USING taker_volume::NUMERIC;
@@ -0,0 +1,5 @@
+-- NULL on candles loaded before these were kept, reloading fills them in.
+ALTER TABLE candles
+  ADD COLUMN IF NOT EXISTS close_time         BIGINT,
+  ADD COLUMN IF NOT EXISTS quote_volume       NUMERIC,
+  ADD COLUMN IF NOT EXISTS taker_quote_volume NUMERIC;
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS ingested_files (
+  path      TEXT PRIMARY KEY,
+  checksum  TEXT NOT NULL,
+  row_count BIGINT NOT NULL DEFAULT 0,
+  status    TEXT NOT NULL,
+  error     TEXT,
+  loaded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS ingested_files_status_idx ON ingested_files (status);
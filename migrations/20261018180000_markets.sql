@@ -0,0 +1,16 @@
+-- Spot and futures symbols share tickers, so the market is part of every key.
+-- Existing rows were all synced from or loaded for spot.
+ALTER TABLE symbols ADD COLUMN IF NOT EXISTS market TEXT NOT NULL DEFAULT 'spot';
+ALTER TABLE symbols
+  DROP CONSTRAINT symbols_pkey,
+  ADD PRIMARY KEY(market, symbol);
+
+ALTER TABLE candles ADD COLUMN IF NOT EXISTS market TEXT NOT NULL DEFAULT 'spot';
+ALTER TABLE candles
+  DROP CONSTRAINT candles_pkey,
+  ADD PRIMARY KEY(market, symbol, interval, open_time);
+
+ALTER TABLE download_cursors ADD COLUMN IF NOT EXISTS market TEXT NOT NULL DEFAULT 'spot';
+ALTER TABLE download_cursors
+  DROP CONSTRAINT download_cursors_pkey,
+  ADD PRIMARY KEY(market, symbol, interval);
@@ -0,0 +1,17 @@
+-- Aggregated trades from the `aggTrades` archives, far too many rows for one table.
+-- The loader creates a partition per month of `transact_time` before loading it.
+CREATE TABLE IF NOT EXISTS agg_trades (
+  market         TEXT NOT NULL,
+  symbol         TEXT NOT NULL,
+  agg_trade_id   BIGINT NOT NULL,
+  price          NUMERIC NOT NULL,
+  quantity       NUMERIC NOT NULL,
+  first_trade_id BIGINT NOT NULL,
+  last_trade_id  BIGINT NOT NULL,
+  transact_time  BIGINT NOT NULL,
+  is_buyer_maker BOOLEAN NOT NULL,
+  PRIMARY KEY(market, symbol, agg_trade_id, transact_time)
+) PARTITION BY RANGE (transact_time);
+
+-- Catches trades outside the monthly partitions, it should stay empty.
+CREATE TABLE IF NOT EXISTS agg_trades_default PARTITION OF agg_trades DEFAULT;
@@ -0,0 +1,39 @@
+-- Rebuilds `candles` partitioned by month of `open_time`, or as a TimescaleDB
+-- hypertable with 30 day chunks when the extension is installed.
+-- The loader creates the monthly partitions it needs before loading.
+ALTER TABLE candles RENAME TO candles_unpartitioned;
+ALTER TABLE candles_unpartitioned RENAME CONSTRAINT candles_pkey TO candles_unpartitioned_pkey;
+
+DO $$
+DECLARE
+  timescale BOOLEAN := EXISTS ( SELECT 1 FROM pg_extension WHERE extname = 'timescaledb' );
+  month DATE;
+BEGIN
+  IF timescale THEN
+    CREATE TABLE candles ( LIKE candles_unpartitioned INCLUDING DEFAULTS );
+  ELSE
+    CREATE TABLE candles ( LIKE candles_unpartitioned INCLUDING DEFAULTS )
+      PARTITION BY RANGE (open_time);
+  END IF;
+  ALTER TABLE candles ADD PRIMARY KEY(market, symbol, interval, open_time);
+
+  IF timescale THEN
+    -- open_time is in milliseconds, so is the chunk interval.
+    PERFORM create_hypertable('candles', 'open_time', chunk_time_interval => 2592000000);
+  ELSE
+    FOR month IN
+      SELECT DISTINCT date_trunc('month', to_timestamp(open_time / 1000) AT TIME ZONE 'UTC')::DATE
+      FROM candles_unpartitioned
+    LOOP
+      EXECUTE format(
+        'CREATE TABLE %I PARTITION OF candles FOR VALUES FROM ( %s ) TO ( %s )',
+        'candles_' || to_char(month, 'YYYY_MM'),
+        extract(epoch FROM month)::BIGINT * 1000,
+        extract(epoch FROM month + INTERVAL '1 month')::BIGINT * 1000
+      );
+    END LOOP;
+  END IF;
+END $$;
+
+INSERT INTO candles SELECT * FROM candles_unpartitioned;
+DROP TABLE candles_unpartitioned;
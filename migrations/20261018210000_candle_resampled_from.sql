@@ -0,0 +1,4 @@
+-- The interval a candle was built from by `history resample`, NULL for candles
+-- loaded from binance.
+ALTER TABLE candles
+  ADD COLUMN IF NOT EXISTS resampled_from TEXT;
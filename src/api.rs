@@ -12,7 +12,9 @@ use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
 use std::sync::Arc;
 use tower_http::{cors::CorsLayer, services::ServeDir, trace::TraceLayer};
 
+mod candles;
 pub mod response;
+mod symbols;
 
 pub struct AppState {
   pool: Pool<Postgres>,
@@ -31,8 +33,11 @@ impl AppState {
   }
 }
 
-pub async fn serve() -> Result<()> {
+pub async fn serve(migrate: bool) -> Result<()> {
   let app_state = Arc::new(AppState::new().await?);
+  if migrate {
+    crate::db::migrate(&app_state.pool).await?;
+  }
   let mut app = router(app_state.clone()).layer(TraceLayer::new_for_http());
 
   if let Some(cors_origin) = app_state.config.cors_origin.as_ref() {
@@ -52,5 +57,8 @@ pub async fn serve() -> Result<()> {
 }
 
 pub fn router(app_state: Arc<AppState>) -> Router {
-  Router::new().nest("/", Router::new()).with_state(app_state)
+  Router::new()
+    .merge(candles::router())
+    .merge(symbols::router())
+    .with_state(app_state)
 }
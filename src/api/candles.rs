@@ -0,0 +1,178 @@
+use crate::{
+  prelude::*,
+  resample::{sources, Resampler},
+};
+use axum::routing::*;
+use chrono_tz::Tz;
+use sqlx::PgPool;
+
+const DEFAULT_LIMIT: i64 = 500;
+const MAX_LIMIT: i64 = 1000;
+/// Bounds the finer candles fetched to resample one page.
+const MAX_RESAMPLE_ROWS: i64 = 200_000;
+
+pub fn router() -> Router<Arc<AppState>> {
+  Router::new().route("/symbols/:symbol/candles", get(candles))
+}
+
+#[derive(Deserialize)]
+pub struct CandleQuery {
+  #[serde(default)]
+  market: Market,
+  /// Any interval like `5m`, `3h` or `1d`. Ones that aren't stored are
+  /// resampled from finer candles
+  interval: Interval,
+  /// Timezone days, weeks and months start in, UTC by default
+  tz: Option<String>,
+  /// Inclusive lower bound on `open_time` (ms)
+  from: Option<i64>,
+  /// Inclusive upper bound on `open_time` (ms)
+  to: Option<i64>,
+  limit: Option<i64>,
+  /// The `next_cursor` of the previous page
+  cursor: Option<i64>,
+}
+
+#[derive(Serialize, Type)]
+pub struct CandlePage {
+  candles: Vec<Candle>,
+  /// `open_time` of the last candle when there are more pages
+  next_cursor: Option<i64>,
+}
+
+async fn candles(
+  State(state): State<Arc<AppState>>,
+  Path(symbol): Path<String>,
+  Query(query): Query<CandleQuery>,
+) -> Result<ApiResponse<CandlePage>, ApiErr> {
+  let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+
+  let mut errors = FieldErrors::new();
+  if !(1..=MAX_LIMIT).contains(&limit) {
+    errors.add_error("limit", &format!("must be between 1 and {MAX_LIMIT}"));
+  }
+  let times = [
+    ("from", query.from),
+    ("to", query.to),
+    ("cursor", query.cursor),
+  ];
+  for (field, time) in times {
+    if time.is_some_and(|time| !(MIN_TIME..=MAX_TIME).contains(&time)) {
+      errors.add_error(field, &format!("must be between {MIN_TIME} and {MAX_TIME}"));
+    }
+  }
+  let tz = match query.tz.as_deref().map(str::parse::<Tz>) {
+    None => Tz::UTC,
+    Some(Ok(tz)) => tz,
+    Some(Err(_)) => {
+      errors.add_error("tz", "unknown timezone");
+      Tz::UTC
+    }
+  };
+  errors?;
+
+  // The cursor is exclusive, so resume right after it.
+  let from = match query.cursor {
+    Some(cursor) => Some(query.from.map_or(cursor + 1, |from| from.max(cursor + 1))),
+    None => query.from,
+  };
+
+  let symbol = symbol.to_uppercase();
+  let stored = tz == Tz::UTC
+    && Candle::has_interval(&state.pool, query.market, &symbol, query.interval)
+      .await
+      .api()?;
+
+  // Fetch one extra candle to know whether there is another page.
+  let mut candles = if stored {
+    Candle::fetch_range(
+      &state.pool,
+      query.market,
+      &symbol,
+      query.interval,
+      from,
+      query.to,
+      limit + 1,
+    )
+    .await
+    .api()?
+  } else {
+    resampled(&state.pool, &query, &symbol, tz, from, limit + 1).await?
+  };
+
+  let mut next_cursor = None;
+  if candles.len() as i64 > limit {
+    candles.truncate(limit as usize);
+    next_cursor = candles.last().map(|c| c.open_time);
+  }
+
+  respond(CandlePage {
+    candles,
+    next_cursor,
+  })
+}
+
+/// Builds up to `limit` candles from the coarsest stored interval that adds up to them.
+async fn resampled(
+  pool: &PgPool,
+  query: &CandleQuery,
+  symbol: &str,
+  tz: Tz,
+  from: Option<i64>,
+  limit: i64,
+) -> Result<Vec<Candle>, ApiErr> {
+  let target = query.interval;
+  let mut source = None;
+  for interval in sources(&target, tz) {
+    if Candle::has_interval(pool, query.market, symbol, interval)
+      .await
+      .api()?
+    {
+      source = Some(interval);
+      break;
+    }
+  }
+  let Some(source) = source else {
+    return Ok(vec![]);
+  };
+
+  // Each candle takes at most `ratio` source candles, which are stored in UTC where
+  // all but months have a fixed length. Two spare candles leave room for the first
+  // and last ones, which can be partial.
+  let source_millis = source
+    .alignment()
+    .map_or(source.max_millis(), |(step, _)| step);
+  let ratio = target.max_millis() / source_millis + 1;
+  let source_limit = (limit + 2) * ratio;
+  if source_limit > MAX_RESAMPLE_ROWS {
+    let mut errors = FieldErrors::new();
+    errors.add_error(
+      "limit",
+      &format!(
+        "at most {} {target} candles can be built from {source} candles at once",
+        (MAX_RESAMPLE_ROWS / ratio - 2).max(0)
+      ),
+    );
+    errors?;
+  }
+
+  let to = query
+    .to
+    .map(|to| target.close_time_in(target.open_time_in(to, &tz), &tz));
+  let rows = Candle::fetch_range(pool, query.market, symbol, source, from, to, source_limit)
+    .await
+    .api()?;
+
+  let mut resampler = Resampler::new(target, tz);
+  let mut candles: Vec<Candle> = rows.iter().filter_map(|c| resampler.push(c)).collect();
+  // Unless the row limit cut it short, the last candle has every row there is.
+  if (rows.len() as i64) < source_limit {
+    candles.extend(resampler.finish());
+  }
+
+  // The first candle is partial when `from` isn't aligned to it.
+  candles.retain(|c| from.is_none_or(|from| c.open_time >= from));
+  candles.truncate(limit as usize);
+
+  Ok(candles)
+}
@@ -210,6 +210,10 @@ impl ErrResidual {
   }
 }
 
+impl<T> std::ops::Residual<T> for ErrResidual {
+  type TryType = PossibleApiErr<T>;
+}
+
 impl<T> Try for PossibleApiErr<T> {
   type Output = T;
   type Residual = ErrResidual;
@@ -35,6 +35,10 @@ impl From<FieldErrors> for ApiErr {
   }
 }
 
+impl std::ops::Residual<FieldErrors> for FieldErrors {
+  type TryType = FieldErrors;
+}
+
 impl std::ops::Try for FieldErrors {
   type Output = Self;
   type Residual = Self;
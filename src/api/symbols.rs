@@ -0,0 +1,79 @@
+use crate::{audit::CandleAudit, prelude::*};
+use axum::routing::*;
+
+pub fn router() -> Router<Arc<AppState>> {
+  Router::new()
+    .route("/symbols", get(index))
+    .route("/symbols/:symbol", get(show))
+    .route("/symbols/:symbol/audit", get(audit))
+}
+
+#[derive(Deserialize)]
+pub struct ShowQuery {
+  #[serde(default)]
+  market: Market,
+}
+
+#[derive(Serialize, Type)]
+pub struct SymbolDetail {
+  symbol: Symbol,
+  /// Stored candle ranges, one entry per interval
+  intervals: Vec<CandleCoverage>,
+}
+
+async fn index(
+  State(state): State<Arc<AppState>>,
+  Query(mut filter): Query<SymbolFilter>,
+) -> Result<ApiResponse<Vec<Symbol>>, ApiErr> {
+  // Binance symbols and assets are always upper case.
+  for value in [
+    &mut filter.status,
+    &mut filter.base_asset,
+    &mut filter.quote_asset,
+    &mut filter.prefix,
+  ]
+  .into_iter()
+  .flatten()
+  {
+    *value = value.to_uppercase();
+  }
+
+  let symbols = Symbol::search(&state.pool, &filter).await.api()?;
+  respond(symbols)
+}
+
+async fn show(
+  State(state): State<Arc<AppState>>,
+  Path(symbol): Path<String>,
+  Query(query): Query<ShowQuery>,
+) -> Result<ApiResponse<SymbolDetail>, ApiErr> {
+  let symbol = Symbol::find(&state.pool, query.market, &symbol.to_uppercase())
+    .await
+    .api()?
+    .api()
+    .status_code(StatusCode::NOT_FOUND)
+    .pub_msg("Symbol not found")?;
+  let intervals = Candle::coverage(&state.pool, query.market, &symbol.symbol)
+    .await
+    .api()?;
+
+  respond(SymbolDetail { symbol, intervals })
+}
+
+async fn audit(
+  State(state): State<Arc<AppState>>,
+  Path(symbol): Path<String>,
+  Query(query): Query<ShowQuery>,
+) -> Result<ApiResponse<CandleAudit>, ApiErr> {
+  let symbol = Symbol::find(&state.pool, query.market, &symbol.to_uppercase())
+    .await
+    .api()?
+    .api()
+    .status_code(StatusCode::NOT_FOUND)
+    .pub_msg("Symbol not found")?;
+  let audit = CandleAudit::run(&state.pool, query.market, &symbol.symbol, None)
+    .await
+    .api()?;
+
+  respond(audit)
+}
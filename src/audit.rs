@@ -0,0 +1,177 @@
+use anyhow::Result;
+use chrono::DateTime;
+use entity::{
+  Candle, CandleGap, DuplicateCandle, Interval, InvalidCandle, Market, VolumeMismatch, AUDIT_LIMIT,
+};
+use serde::Serialize;
+use specta::Type;
+use sqlx::PgPool;
+
+/// `(fine, coarse)` intervals whose volumes are compared when both are stored.
+const VOLUME_CHECKS: &[(Interval, Interval)] = &[
+  (Interval::Minutes(1), Interval::Hours(1)),
+  (Interval::Hours(1), Interval::Days(1)),
+  (Interval::Days(1), Interval::Months(1)),
+];
+
+/// Holes and inconsistencies in the stored candles of a symbol.
+/// Each list holds at most `AUDIT_LIMIT` entries.
+#[derive(Serialize, Type)]
+pub struct CandleAudit {
+  pub market: Market,
+  pub symbol: String,
+  pub intervals: Vec<IntervalAudit>,
+  pub invalid: Vec<InvalidCandle>,
+  pub duplicates: Vec<DuplicateCandle>,
+  pub volume_mismatches: Vec<VolumeMismatch>,
+}
+
+#[derive(Serialize, Type)]
+pub struct IntervalAudit {
+  pub interval: Interval,
+  pub count: i64,
+  pub gaps: Vec<CandleGap>,
+  /// `open_time`s that don't start a candle of the interval
+  pub misaligned: Vec<i64>,
+}
+
+impl CandleAudit {
+  /// Audits the stored intervals of a symbol, or only `intervals` among them.
+  pub async fn run(
+    pool: &PgPool,
+    market: Market,
+    symbol: &str,
+    intervals: Option<&[Interval]>,
+  ) -> Result<Self> {
+    let coverage = Candle::coverage(pool, market, symbol).await?;
+    let stored: Vec<Interval> = coverage
+      .iter()
+      .map(|c| c.interval)
+      .filter(|i| intervals.is_none_or(|intervals| intervals.contains(i)))
+      .collect();
+
+    let mut audit = Self {
+      market,
+      symbol: symbol.to_string(),
+      intervals: vec![],
+      invalid: Candle::invalid(pool, market, symbol).await?,
+      duplicates: Candle::duplicates(pool, market, symbol).await?,
+      volume_mismatches: vec![],
+    };
+
+    for coverage in coverage.iter().filter(|c| stored.contains(&c.interval)) {
+      let interval = coverage.interval;
+      audit.intervals.push(IntervalAudit {
+        interval,
+        count: Candle::count(pool, market, symbol, interval).await?,
+        gaps: Candle::gaps(pool, market, symbol, interval).await?,
+        misaligned: Candle::misaligned(pool, market, symbol, interval).await?,
+      });
+    }
+
+    for &(fine, coarse) in VOLUME_CHECKS {
+      if !stored.contains(&fine) || !stored.contains(&coarse) {
+        continue;
+      }
+      audit
+        .volume_mismatches
+        .extend(Candle::volume_mismatches(pool, market, symbol, coarse, fine).await?);
+    }
+
+    Ok(audit)
+  }
+
+  pub fn problems(&self) -> usize {
+    let interval_problems: usize = self
+      .intervals
+      .iter()
+      .map(|i| i.gaps.len() + i.misaligned.len())
+      .sum();
+    interval_problems + self.invalid.len() + self.duplicates.len() + self.volume_mismatches.len()
+  }
+
+  pub fn print(&self) {
+    println!(
+      "{} {}: {} problems",
+      self.market,
+      self.symbol,
+      self.problems()
+    );
+    for interval in &self.intervals {
+      println!(
+        "  {}: {} candles, {} gaps, {} misaligned",
+        interval.interval,
+        interval.count,
+        limited(interval.gaps.len()),
+        limited(interval.misaligned.len())
+      );
+      for gap in &interval.gaps {
+        println!(
+          "    missing between {} and {}",
+          time(gap.after),
+          time(gap.before)
+        );
+      }
+      for open_time in &interval.misaligned {
+        println!("    misaligned {}", time(*open_time));
+      }
+    }
+
+    if !self.invalid.is_empty() {
+      println!("  Invalid: {}", limited(self.invalid.len()));
+      for candle in &self.invalid {
+        println!(
+          "    {} {}: {}",
+          candle.interval,
+          time(candle.open_time),
+          candle.problems.join(", ")
+        );
+      }
+    }
+    if !self.duplicates.is_empty() {
+      println!(
+        "  Duplicated across intervals: {}",
+        limited(self.duplicates.len())
+      );
+      for candle in &self.duplicates {
+        println!(
+          "    {} = {} {}",
+          candle.interval,
+          candle.other_interval,
+          time(candle.open_time)
+        );
+      }
+    }
+    if !self.volume_mismatches.is_empty() {
+      println!(
+        "  Volume mismatches: {}",
+        limited(self.volume_mismatches.len())
+      );
+      for mismatch in &self.volume_mismatches {
+        println!(
+          "    {} {}: {} vs {} summed from {}",
+          mismatch.interval,
+          time(mismatch.open_time),
+          mismatch.volume,
+          mismatch.fine_volume,
+          mismatch.fine_interval
+        );
+      }
+    }
+  }
+}
+
+/// Counts that hit `AUDIT_LIMIT` may be higher.
+fn limited(count: usize) -> String {
+  if count as i64 >= AUDIT_LIMIT {
+    format!("{count}+")
+  } else {
+    count.to_string()
+  }
+}
+
+fn time(millis: i64) -> String {
+  DateTime::from_timestamp_millis(millis)
+    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+    .unwrap_or_else(|| millis.to_string())
+}
@@ -1,3 +1,5 @@
+use entity::Market;
+
 pub struct Config {
   pub database_url: String,
   pub host: String,
@@ -16,6 +18,45 @@ impl Config {
   }
 }
 
+/// Where binance data comes from. Either url can also be a `file://` url or a
+/// directory laid out like the remote, to run from a local mirror.
+/// Separate from `Config`, only the CLI jobs talk to binance and they don't need
+/// the server's settings.
+pub struct Sources {
+  /// Serves `/api/v3/exchangeInfo`
+  pub binance_api_url: String,
+  /// Serves `/fapi/v1/exchangeInfo`
+  pub binance_usdm_api_url: String,
+  /// Serves `/dapi/v1/exchangeInfo`
+  pub binance_coinm_api_url: String,
+  /// Serves the `spot/...` and `futures/...` archive trees
+  pub binance_data_url: String,
+}
+
+impl Sources {
+  pub fn init() -> Self {
+    Self {
+      binance_api_url: opt_var("BINANCE_API_URL")
+        .unwrap_or_else(|| "https://api.binance.com".to_string()),
+      binance_usdm_api_url: opt_var("BINANCE_USDM_API_URL")
+        .unwrap_or_else(|| "https://fapi.binance.com".to_string()),
+      binance_coinm_api_url: opt_var("BINANCE_COINM_API_URL")
+        .unwrap_or_else(|| "https://dapi.binance.com".to_string()),
+      binance_data_url: opt_var("BINANCE_DATA_URL")
+        .unwrap_or_else(|| "https://data.binance.vision/data".to_string()),
+    }
+  }
+
+  /// The API host serving a market's `exchangeInfo`.
+  pub fn api_url(&self, market: Market) -> &str {
+    match market {
+      Market::Spot => &self.binance_api_url,
+      Market::UsdM => &self.binance_usdm_api_url,
+      Market::CoinM => &self.binance_coinm_api_url,
+    }
+  }
+}
+
 fn var(key: &str) -> String {
   std::env::var(key).expect(&format!("{key} must be set"))
 }
@@ -1,12 +1,99 @@
-use anyhow::Result;
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Datelike, Months};
+use sqlx::{migrate::Migrator, postgres::PgPoolOptions, PgPool};
 use std::env;
 
+/// The migrations in `./migrations`, embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Connections kept beside the ones of concurrent tasks, for the queries a job runs
+/// around them.
+const SPARE_CONNECTIONS: u32 = 10;
+
 pub async fn pool() -> Result<PgPool> {
+  connect(40).await
+}
+
+/// A pool with a connection for each of `tasks` running at once, so that none of
+/// them waits on the others to acquire one.
+pub async fn pool_for(tasks: usize) -> Result<PgPool> {
+  connect(u32::try_from(tasks)?.saturating_add(SPARE_CONNECTIONS)).await
+}
+
+async fn connect(max_connections: u32) -> Result<PgPool> {
   Ok(
     PgPoolOptions::new()
-      .max_connections(40)
+      .max_connections(max_connections)
       .connect(&env::var("DATABASE_URL").unwrap())
       .await?,
   )
 }
+
+pub async fn migrate(pool: &PgPool) -> Result<()> {
+  MIGRATOR.run(pool).await?;
+  Ok(())
+}
+
+/// EXPLAINs a one month range query on `candles` and prints the partitions (or
+/// TimescaleDB chunks) it scans, failing when it doesn't prune the others.
+pub async fn check_partition_pruning(pool: &PgPool) -> Result<()> {
+  let partitions: Vec<String> = sqlx::query!(
+    r#"--sql
+SELECT c.relname::TEXT AS "relname!"
+FROM pg_inherits i
+JOIN pg_class c ON c.oid = i.inhrelid
+WHERE i.inhparent = 'candles'::regclass
+ORDER BY c.relname;
+    "#
+  )
+  .fetch_all(pool)
+  .await?
+  .into_iter()
+  .map(|r| r.relname)
+  .collect();
+
+  let Some(latest) = sqlx::query_scalar!("SELECT MAX(open_time) FROM candles;")
+    .fetch_one(pool)
+    .await?
+  else {
+    bail!("No candles to query");
+  };
+  let month = DateTime::from_timestamp_millis(latest)
+    .unwrap_or_default()
+    .date_naive()
+    .with_day(1)
+    .unwrap();
+  let from = month.and_hms_opt(0, 0, 0).unwrap().and_utc();
+  let to = from + Months::new(1);
+
+  // Literal bounds, so the planner prunes rather than the executor.
+  let plan: Vec<String> = sqlx::query_scalar(&format!(
+    "EXPLAIN SELECT * FROM candles WHERE open_time >= {} AND open_time < {};",
+    from.timestamp_millis(),
+    to.timestamp_millis()
+  ))
+  .fetch_all(pool)
+  .await?;
+
+  let mut scanned: Vec<&str> = plan
+    .iter()
+    .filter_map(|line| line.split(" on ").nth(1)?.split_whitespace().next())
+    .filter(|relation| partitions.iter().any(|p| p == relation))
+    .collect();
+  scanned.sort();
+  scanned.dedup();
+
+  println!(
+    "A range query over {} scans {} of {} partitions: {}",
+    month.format("%Y-%m"),
+    scanned.len(),
+    partitions.len(),
+    scanned.join(", ")
+  );
+  // A month overlaps a single monthly partition, or at most two 30 day chunks.
+  if partitions.is_empty() || scanned.len() > 2 {
+    bail!("Range queries on candles aren't pruned, is the table partitioned?");
+  }
+
+  Ok(())
+}
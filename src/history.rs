@@ -1,165 +1,1066 @@
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use async_zip::tokio::read::seek::ZipFileReader;
-use entity::{Candle, Symbol};
-use futures::StreamExt;
-use sqlx::PgPool;
-use std::{ops::RangeInclusive, path::PathBuf};
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+use clap::builder::RangedU64ValueParser;
+use entity::{
+  month_millis, AggTrade, Candle, DownloadCursor, IngestedFile, Interval, Market, Symbol,
+  SymbolFilter, INGESTED_FAILED,
+};
+use futures::{io::AsyncBufReadExt, StreamExt};
+use sqlx::{PgConnection, PgPool};
+use std::{
+  collections::{BTreeSet, HashMap},
+  fmt,
+  path::{Path, PathBuf},
+  pin::pin,
+  str::FromStr,
+  time::Instant,
+};
 use tokio::{
-  fs::{create_dir_all, File},
-  io::{AsyncWriteExt, BufReader},
-  spawn,
+  fs::{create_dir_all, read_dir, read_to_string, remove_file, rename, write, File},
+  io::BufReader,
+  signal,
+  sync::OnceCell,
+  task::JoinSet,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-const INTERVALS: &[&str] = &["15m", "30m", "1h", "2h", "4h", "12h", "1d", "1w", "1mo"];
-const BASEURL: &str = "https://data.binance.vision/data/spot/monthly/klines";
-static YEARS: RangeInclusive<i32> = 2017..=2024;
-static MONTHS: RangeInclusive<i32> = 1..=12;
+use crate::{audit::CandleAudit, config::Sources, resample::Resampler};
 
-pub async fn load_btc_usdt(pool: &PgPool) -> Result<()> {
-  let symbols = Symbol::fetch_btc_usdt_pairs(pool).await?;
-  let mut futures = vec![];
+mod agg_trade;
+mod checksum;
+mod client;
+mod kline;
+mod period;
+mod report;
+
+use agg_trade::AggTradeParser;
+use client::{Client, DEFAULT_RETRIES};
+pub(crate) use kline::KlineParser;
+use period::{days, first_of_month, months, Period};
+use report::{Report, Status};
+
+const INTERVALS: [Interval; 9] = [
+  Interval::Minutes(15),
+  Interval::Minutes(30),
+  Interval::Hours(1),
+  Interval::Hours(2),
+  Interval::Hours(4),
+  Interval::Hours(12),
+  Interval::Days(1),
+  Interval::Weeks(1),
+  Interval::Months(1),
+];
+/// Bounds the memory used per archive while loading.
+const LOAD_BATCH_LINES: usize = 10_000;
+const VERIFY_CONCURRENCY: usize = 8;
+const RESAMPLE_BATCH: i64 = 50_000;
+/// Binance opened in July 2017, nothing is archived before that.
+const FIRST_MONTH: NaiveDate = NaiveDate::from_ymd_opt(2017, 7, 1).unwrap();
+
+/// Selects what the history jobs download and load.
+#[derive(clap::Args, Debug)]
+pub struct HistoryArgs {
+  /// spot, um (USDⓈ-M futures) or cm (COIN-M futures)
+  #[arg(long, default_value_t)]
+  market: Market,
+
+  /// Which archives to download or load
+  #[arg(long, value_enum, default_value_t = Dataset::Klines)]
+  dataset: Dataset,
+
+  /// Only these symbols (comma separated), regardless of their status.
+  /// Defaults to every TRADING symbol
+  #[arg(long, value_delimiter = ',')]
+  symbols: Vec<String>,
+
+  /// Only symbols quoted in one of these assets (comma separated)
+  #[arg(long = "quote-asset", value_delimiter = ',')]
+  quote_assets: Vec<String>,
+
+  /// Candle intervals (comma separated). 1s (spot only) and 1m are published too,
+  /// but only downloaded when listed as they are large. Ignored for aggTrades
+  #[arg(long, value_delimiter = ',', default_values_t = INTERVALS)]
+  intervals: Vec<Interval>,
+
+  /// First month (YYYY-MM). Downloads default to where the last run stopped
+  #[arg(long, value_parser = parse_month)]
+  from: Option<NaiveDate>,
+
+  /// Last month (YYYY-MM). Defaults to the current month
+  #[arg(long, value_parser = parse_month)]
+  to: Option<NaiveDate>,
+
+  /// How many archives are downloaded or loaded at once, each with its own database
+  /// connection
+  #[arg(
+    long,
+    default_value_t = 30,
+    value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+  )]
+  pub concurrency: usize,
+
+  /// How many times a failed request is retried before the file is given up on
+  #[arg(long, default_value_t = DEFAULT_RETRIES)]
+  retries: u32,
+
+  /// Also write the outcome of every file as JSON to this path
+  #[arg(long)]
+  pub report: Option<PathBuf>,
+}
+
+impl HistoryArgs {
+  async fn symbols(&self, pool: &PgPool) -> Result<Vec<Symbol>> {
+    let symbols: Vec<_> = self.symbols.iter().map(|s| s.to_uppercase()).collect();
+    let quote_assets: Vec<_> = self.quote_assets.iter().map(|s| s.to_uppercase()).collect();
+
+    let filter = SymbolFilter {
+      market: Some(self.market),
+      status: symbols.is_empty().then(|| "TRADING".to_string()),
+      ..Default::default()
+    };
+    let mut found = Symbol::search(pool, &filter).await?;
+
+    if !symbols.is_empty() {
+      found.retain(|s| symbols.contains(&s.symbol));
+    }
+    if !quote_assets.is_empty() {
+      found.retain(|s| quote_assets.contains(&s.quote_asset));
+    }
+
+    Ok(found)
+  }
+
+  /// The archive trees selected under each symbol.
+  fn series(&self) -> Vec<Series> {
+    match self.dataset {
+      Dataset::Klines => self.intervals.iter().copied().map(Series::Klines).collect(),
+      Dataset::AggTrades => vec![Series::AggTrades],
+    }
+  }
+
+  fn to(&self) -> NaiveDate {
+    self
+      .to
+      .unwrap_or_else(|| first_of_month(Utc::now().date_naive()))
+  }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Dataset {
+  /// Candles, one archive tree per interval
+  Klines,
+  /// Aggregated trades
+  AggTrades,
+}
+
+/// The archives kept in one directory under a symbol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Series {
+  Klines(Interval),
+  AggTrades,
+}
+
+/// The directory name, also the key of the series' download cursor.
+impl fmt::Display for Series {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Series::Klines(interval) => write!(f, "{interval}"),
+      Series::AggTrades => f.write_str("aggTrades"),
+    }
+  }
+}
+
+impl FromStr for Series {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "aggTrades" => Ok(Series::AggTrades),
+      _ => Ok(Series::Klines(s.parse()?)),
+    }
+  }
+}
+
+/// Builds candles of coarser intervals from finer stored ones.
+#[derive(clap::Args, Debug)]
+pub struct ResampleArgs {
+  #[command(flatten)]
+  history: HistoryArgs,
+
+  /// The stored interval to resample from
+  #[arg(long, default_value = "1m")]
+  source: Interval,
+
+  /// Compare the result with the candles already stored for the intervals, e.g.
+  /// binance's own 1h and 1d, instead of storing it
+  #[arg(long)]
+  check: bool,
+}
+
+fn parse_month(value: &str) -> Result<NaiveDate, String> {
+  NaiveDate::parse_from_str(&format!("{value}-01"), "%Y-%m-%d")
+    .map_err(|_| format!("{value:?} is not a YYYY-MM month"))
+}
 
-  // We know it'll last long enough.
-  let pool = unsafe { std::mem::transmute::<&'_ PgPool, &'static PgPool>(pool) };
+pub async fn load_history_all(pool: &PgPool, args: &HistoryArgs) -> Result<Report> {
+  let symbols = args.symbols(pool).await?;
+  let from = args.from.unwrap_or(FIRST_MONTH);
+  let to = args.to();
 
+  let mut jobs = vec![];
   for symbol in symbols {
-    info!("Loading {}...", &symbol.symbol);
-
-    let dir = PathBuf::from("history").join(&symbol.symbol);
-    for interval in INTERVALS {
-      let dir = dir.join(interval);
-      for year in YEARS.clone() {
-        futures.push(load_year(
-          pool,
-          dir.clone(),
-          symbol.symbol.clone(),
-          interval,
+    for series in args.series() {
+      for year in from.year()..=to.year() {
+        jobs.push(LoadJob {
+          market: args.market,
+          symbol: symbol.symbol.clone(),
+          series,
           year,
-        ));
+          months: months(from, to).filter(|m| m.year() == year).collect(),
+        });
       }
     }
   }
 
-  let mut stream_of_futures = futures::stream::iter(futures).buffer_unordered(30);
-  while let Some(_) = stream_of_futures.next().await {}
+  // Partitions are created up front, concurrent loads would race to create them.
+  let mut partitions = BTreeSet::new();
+  for job in &jobs {
+    let dir = archive_dir(job.market, &job.symbol, job.series);
+    for month in &job.months {
+      if !archives(&dir, &[*month]).is_empty() {
+        partitions.insert(*month);
+      }
+    }
+  }
+  for month in partitions {
+    match args.dataset {
+      Dataset::Klines => Candle::ensure_partition(pool, month).await?,
+      Dataset::AggTrades => AggTrade::ensure_partition(pool, month).await?,
+    }
+  }
 
-  Ok(())
+  let mut jobs = jobs.into_iter();
+
+  let mut report = Report::new("history load");
+  let mut tasks = JoinSet::new();
+  // The archive dir and name of each running job, to report the ones that panic.
+  let mut running = HashMap::new();
+  let mut ctrl_c = pin!(signal::ctrl_c());
+  loop {
+    while tasks.len() < args.concurrency {
+      let Some(job) = jobs.next() else {
+        break;
+      };
+      let dir = archive_dir(job.market, &job.symbol, job.series);
+      let name = job.to_string();
+      let task = tasks.spawn(load_year(pool.clone(), job));
+      running.insert(task.id(), (dir, name));
+    }
+
+    let result = tokio::select! {
+      _ = &mut ctrl_c => {
+        warn!("Interrupted, cancelling {} loads...", tasks.len());
+        // Dropping the loads' transactions rolls them back.
+        tasks.shutdown().await;
+        bail!("Interrupted");
+      }
+      result = tasks.join_next_with_id() => result,
+    };
+    let Some(result) = result else {
+      break;
+    };
+
+    match result {
+      Ok((id, year_report)) => {
+        running.remove(&id);
+        report.extend(year_report);
+      }
+      Err(err) => {
+        let (dir, name) = running
+          .remove(&err.id())
+          .expect("spawned loads are tracked");
+        let err = anyhow!("load panicked: {err}").context(name);
+        error!("{err:?}");
+        report.fail(&dir, &err);
+      }
+    }
+  }
+
+  Ok(report)
 }
 
-async fn load_year<'a>(
-  pool: &'a PgPool,
-  dir: PathBuf,
+/// One symbol/series/year of archives, the unit loads run in parallel by.
+struct LoadJob {
+  market: Market,
   symbol: String,
-  interval: &str,
+  series: Series,
   year: i32,
-) -> Result<()> {
-  let mut tx = pool.begin().await?;
-  for month in MONTHS.clone() {
-    let zip_path = dir.join(format!("{year}-{month:02}.zip"));
-    info!("{zip_path:?}");
-    if !zip_path.exists() {
+  months: Vec<NaiveDate>,
+}
+
+impl fmt::Display for LoadJob {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} {} {} {}",
+      self.market, self.symbol, self.series, self.year
+    )
+  }
+}
+
+async fn load_year(pool: PgPool, job: LoadJob) -> Report {
+  let mut report = Report::default();
+  let dir = archive_dir(job.market, &job.symbol, job.series);
+  for zip_path in archives(&dir, &job.months) {
+    match load_file(&pool, &zip_path, job.market, &job.symbol, job.series).await {
+      Ok(status) => report.record(&zip_path, status),
+      Err(err) => {
+        let err = err.context(job.to_string());
+        error!("{err:?}");
+        report.fail(&zip_path, &err);
+      }
+    }
+  }
+  report
+}
+
+/// The archives on disk covering `months`: the monthly archive when there is
+/// one, the daily archives otherwise.
+fn archives(dir: &Path, months: &[NaiveDate]) -> Vec<PathBuf> {
+  let mut archives = vec![];
+  for month in months {
+    let monthly = Period::Month(*month).path(dir);
+    if monthly.exists() {
+      archives.push(monthly);
       continue;
     }
-    // let csv_path = format!("{}-{interval}-{year}-{month:02}.csv", &symbol);
 
-    let mut zip_file = BufReader::new(File::open(zip_path).await?);
-    let mut zip = ZipFileReader::with_tokio(&mut zip_file).await?;
+    let last_day = (*month + Months::new(1)).pred_opt().unwrap();
+    for day in days(*month, last_day) {
+      let daily = Period::Day(day).path(dir);
+      if daily.exists() {
+        archives.push(daily);
+      }
+    }
+  }
+  archives
+}
 
-    let mut csv_reader = zip.reader_with_entry(0).await?;
-    let mut csv = String::new();
-    csv_reader.read_to_string_checked(&mut csv).await?;
+/// Loads an archive in its own transaction unless the same file was already loaded,
+/// recording the outcome in `ingested_files`.
+async fn load_file(
+  pool: &PgPool,
+  zip_path: &Path,
+  market: Market,
+  symbol: &str,
+  series: Series,
+) -> Result<Status> {
+  let path = zip_path.to_string_lossy();
+  let checksum = archive_checksum(zip_path).await?;
+  if let Some(file) = IngestedFile::find(pool, &path).await? {
+    if file.is_loaded(&checksum) {
+      return Ok(Status::Skipped);
+    }
+  }
 
-    let lines: Vec<&str> = csv.split("\n").collect();
-    for line in lines {
-      if line.len() == 0 {
-        continue;
-      }
+  let mut tx = pool.begin().await?;
+  match load_archive(&mut tx, zip_path, market, symbol, series).await {
+    Ok(rows) => {
+      IngestedFile::mark_loaded(&mut tx, &path, &checksum, rows as i64).await?;
+      tx.commit().await?;
+      Ok(Status::Ok)
+    }
+    Err(err) => {
+      tx.rollback().await?;
+      IngestedFile::mark_failed(pool, &path, &checksum, &format!("{err:#}")).await?;
+      Err(err)
+    }
+  }
+}
 
-      let split: Vec<&str> = line.split(",").collect();
+/// The verified checksum kept next to the archive, or the archive's own digest.
+async fn archive_checksum(zip_path: &Path) -> Result<String> {
+  match read_to_string(checksum::sidecar_path(zip_path)).await {
+    Ok(body) => checksum::parse_checksum(&body),
+    Err(_) => checksum::sha256_file(zip_path).await,
+  }
+}
 
-      let candle = Candle {
-        symbol: symbol.to_string(),
-        interval: interval.to_string(),
-        open_time: split[0].parse()?,
-        open: split[1].parse()?,
-        high: split[2].parse()?,
-        low: split[3].parse()?,
-        close: split[4].parse()?,
-        volume: split[5].parse()?,
-        num_trades: split[8].parse()?,
-        taker_volume: split[9].parse()?,
-      };
+/// Prints the archives of the selection that still need loading and the ones that failed.
+pub async fn report_history_status(pool: &PgPool, args: &HistoryArgs) -> Result<()> {
+  let ingested: HashMap<String, IngestedFile> = IngestedFile::fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|f| (f.path.clone(), f))
+    .collect();
 
-      candle.insert(&mut tx).await?;
+  let from = args.from.unwrap_or(FIRST_MONTH);
+  let selected: Vec<NaiveDate> = months(from, args.to()).collect();
+
+  let (mut loaded, mut pending, mut failed) = (0, vec![], vec![]);
+  for symbol in args.symbols(pool).await? {
+    for series in args.series() {
+      let dir = archive_dir(args.market, &symbol.symbol, series);
+      for zip_path in archives(&dir, &selected) {
+        let path = zip_path.to_string_lossy().to_string();
+        let checksum = archive_checksum(&zip_path).await?;
+        match ingested.get(&path) {
+          Some(file) if file.is_loaded(&checksum) => loaded += 1,
+          Some(file) if file.status == INGESTED_FAILED => failed.push(file),
+          _ => pending.push(path),
+        }
+      }
     }
   }
-  tx.commit().await?;
+
+  println!("Loaded: {loaded}");
+  println!("Pending: {}", pending.len());
+  for path in &pending {
+    println!("  {path}");
+  }
+  println!("Failed: {}", failed.len());
+  for file in &failed {
+    println!(
+      "  {} ({}): {}",
+      file.path,
+      file.loaded_at,
+      file.error.as_deref().unwrap_or_default()
+    );
+  }
+
   Ok(())
 }
 
-pub async fn download_history_all(pool: &PgPool) -> Result<()> {
-  let symbols = Symbol::fetch_all(pool).await?;
+/// Streams an archive into the database `LOAD_BATCH_LINES` lines at a time.
+/// Returns the number of rows in the archive.
+async fn load_archive(
+  conn: &mut PgConnection,
+  zip_path: &Path,
+  market: Market,
+  symbol: &str,
+  series: Series,
+) -> Result<usize> {
+  let started = Instant::now();
 
-  for symbol in symbols {
-    info!("Downloading {}...", symbol.symbol);
-    let mut futures = vec![];
-    for interval in INTERVALS {
-      for year in YEARS.clone() {
-        for month in MONTHS.clone() {
-          futures.push(spawn(download_month(
-            symbol.symbol.clone(),
-            *interval,
-            year,
-            month,
-          )));
+  let mut zip_file = BufReader::new(File::open(zip_path).await?);
+  let mut zip = ZipFileReader::with_tokio(&mut zip_file).await?;
+
+  // Archives are checked against their SHA-256 when downloaded, so the
+  // entry's CRC isn't checked again here.
+  let mut lines = pin!(futures::io::BufReader::new(zip.reader_with_entry(0).await?));
+  let mut parser = ArchiveParser::new(market, symbol, series);
+
+  let (mut rows, mut written) = (0, 0);
+  let mut batch = vec![];
+  let mut batch_lines = 0;
+  loop {
+    let read = lines.read_until(b'\n', &mut batch).await?;
+    if read > 0 {
+      batch_lines += 1;
+    }
+
+    if batch_lines == LOAD_BATCH_LINES || (read == 0 && !batch.is_empty()) {
+      let (parsed, inserted) = parser
+        .load(conn, &batch)
+        .await
+        .with_context(|| format!("{zip_path:?}"))?;
+      rows += parsed;
+      written += inserted;
+
+      batch.clear();
+      batch_lines = 0;
+    }
+
+    if read == 0 {
+      break;
+    }
+  }
+
+  let elapsed = started.elapsed().as_secs_f64();
+  info!(
+    "Loaded {zip_path:?}: {rows} rows, {written} written in {elapsed:.2}s ({:.0} rows/s)",
+    rows as f64 / elapsed
+  );
+
+  Ok(rows)
+}
+
+/// Parses batches of archive lines and inserts them where that kind of archive goes.
+enum ArchiveParser {
+  Klines(KlineParser),
+  AggTrades(AggTradeParser),
+}
+
+impl ArchiveParser {
+  fn new(market: Market, symbol: &str, series: Series) -> Self {
+    match series {
+      Series::Klines(interval) => Self::Klines(KlineParser::new(market, symbol, interval)),
+      Series::AggTrades => Self::AggTrades(AggTradeParser::new(market, symbol)),
+    }
+  }
+
+  /// Returns the number of rows parsed and written.
+  async fn load(&mut self, conn: &mut PgConnection, batch: &[u8]) -> Result<(usize, u64)> {
+    match self {
+      Self::Klines(parser) => {
+        let candles = parser.parse(batch)?;
+        Ok((candles.len(), Candle::bulk_insert(conn, &candles).await?))
+      }
+      Self::AggTrades(parser) => {
+        let trades = parser.parse(batch)?;
+        Ok((trades.len(), AggTrade::bulk_insert(conn, &trades).await?))
+      }
+    }
+  }
+}
+
+pub async fn download_history_all(
+  pool: &PgPool,
+  sources: &Sources,
+  args: &HistoryArgs,
+) -> Result<Report> {
+  let symbols = args.symbols(pool).await?;
+  let client = Client::new(&sources.binance_data_url, args.concurrency, args.retries);
+  // Looked up once per symbol, and only for series that have no cursor yet.
+  let first_months: Vec<OnceCell<NaiveDate>> = symbols.iter().map(|_| OnceCell::new()).collect();
+
+  let mut futures = vec![];
+  for (symbol, first_month) in symbols.iter().zip(&first_months) {
+    for series in args.series() {
+      futures.push(download_series(
+        pool,
+        &client,
+        sources,
+        first_month,
+        &symbol.symbol,
+        series,
+        args,
+      ));
+    }
+  }
+
+  let mut report = Report::new("history download");
+  let mut stream_of_futures = futures::stream::iter(futures).buffer_unordered(args.concurrency);
+  while let Some(series_report) = stream_of_futures.next().await {
+    report.extend(series_report);
+  }
+
+  Ok(report)
+}
+
+/// Downloads every archive of a symbol/series that isn't on disk yet:
+/// monthly archives after the stored cursor, then daily archives for the
+/// months that don't have a monthly one published yet.
+async fn download_series(
+  pool: &PgPool,
+  client: &Client,
+  sources: &Sources,
+  first_month: &OnceCell<NaiveDate>,
+  symbol: &str,
+  series: Series,
+  args: &HistoryArgs,
+) -> Report {
+  let market = args.market;
+  info!("Downloading {market} {symbol} {series}...");
+  let mut report = Report::default();
+  let dir = archive_dir(market, symbol, series);
+
+  let today = Utc::now().date_naive();
+  let this_month = first_of_month(today);
+  let last_month = this_month - Months::new(1);
+  let to = args.to();
+
+  // An explicit --from is a one-off range, so it doesn't move the cursor.
+  let (start, track) = match args.from {
+    Some(from) => (from, false),
+    None => match DownloadCursor::find(pool, market, symbol, &series.to_string()).await {
+      Ok(Some(cursor)) => (cursor.fetched_through + Months::new(1), true),
+      Ok(None) => {
+        let first = first_month
+          .get_or_init(|| listing_month(sources, market, symbol))
+          .await;
+        (*first, true)
+      }
+      Err(err) => {
+        report.fail(&dir, &err);
+        return report;
+      }
+    },
+  };
+
+  let mut fetched_through = None;
+  let mut complete = true;
+  let mut daily_months = vec![];
+
+  for month in months(start, to.min(last_month)) {
+    let period = Period::Month(month);
+    let zip_path = period.path(&dir);
+    match download_archive(client, market, symbol, series, period).await {
+      Ok(status @ (Status::Ok | Status::Skipped)) => {
+        report.record(&zip_path, status);
+        remove_daily_archives(&dir, month).await;
+      }
+      // Last month's archive is published a few days into this month.
+      Ok(_) if month == last_month => {
+        daily_months.push(month);
+        complete = false;
+      }
+      Ok(status) => report.record(&zip_path, status),
+      Err(err) => {
+        error!("{zip_path:?}: {err:?}");
+        report.fail(&zip_path, &err);
+        complete = false;
+      }
+    }
+    if complete {
+      fetched_through = Some(month);
+    }
+  }
+  if to >= this_month {
+    daily_months.push(this_month);
+  }
+
+  if let (true, Some(fetched_through)) = (track, fetched_through) {
+    let key = series.to_string();
+    if let Err(err) = DownloadCursor::save(pool, market, symbol, &key, fetched_through).await {
+      report.fail(&dir, &err);
+    }
+  }
+
+  // Daily archives are published the day after, so today is never available.
+  let yesterday = today.pred_opt().unwrap();
+  for month in daily_months {
+    let last_day = (month + Months::new(1)).pred_opt().unwrap().min(yesterday);
+    for day in days(month, last_day) {
+      let period = Period::Day(day);
+      let zip_path = period.path(&dir);
+      match download_archive(client, market, symbol, series, period).await {
+        Ok(status) => report.record(&zip_path, status),
+        Err(err) => {
+          error!("{zip_path:?}: {err:?}");
+          report.fail(&zip_path, &err);
         }
       }
     }
-    for future in futures {
-      if let Err(err) = future.await? {
-        error!("{err:?}");
-      };
+  }
+
+  report
+}
+
+/// The month binance started publishing a symbol, from its first monthly candle on
+/// the market's API, so a new symbol doesn't probe every month since `FIRST_MONTH`.
+/// Falls back to `FIRST_MONTH` when the API can't tell, e.g. when it is a local mirror.
+async fn listing_month(sources: &Sources, market: Market, symbol: &str) -> NaiveDate {
+  match first_candle(sources, market, symbol).await {
+    Ok(Some(open_time)) => month_of(open_time).max(FIRST_MONTH),
+    Ok(None) => FIRST_MONTH,
+    Err(err) => {
+      warn!("Can't tell when {market} {symbol} was listed, starting from {FIRST_MONTH}: {err:#}");
+      FIRST_MONTH
     }
   }
+}
 
-  Ok(())
+/// `open_time` of the symbol's first monthly candle, `None` from a local mirror.
+async fn first_candle(sources: &Sources, market: Market, symbol: &str) -> Result<Option<i64>> {
+  let api_url = sources.api_url(market).trim_end_matches('/');
+  if !api_url.starts_with("http://") && !api_url.starts_with("https://") {
+    return Ok(None);
+  }
+
+  let url = format!(
+    "{api_url}/{}?symbol={symbol}&interval=1M&startTime=0&limit=1",
+    market.klines_path()
+  );
+  // Each kline is an array starting with its open time.
+  let klines: Vec<Vec<serde_json::Value>> =
+    reqwest::get(&url).await?.error_for_status()?.json().await?;
+  Ok(
+    klines
+      .first()
+      .and_then(|k| k.first())
+      .and_then(|t| t.as_i64()),
+  )
+}
+
+/// Daily archives are superseded once the month's archive is downloaded.
+async fn remove_daily_archives(dir: &Path, month: NaiveDate) {
+  let Ok(mut entries) = read_dir(dir).await else {
+    return;
+  };
+  while let Ok(Some(entry)) = entries.next_entry().await {
+    let name = entry.file_name().to_string_lossy().to_string();
+    let zip = name.strip_suffix(".CHECKSUM").unwrap_or(&name);
+    if let Some(Period::Day(day)) = Period::from_file_name(zip) {
+      if first_of_month(day) == month {
+        let _ = remove_file(entry.path()).await;
+      }
+    }
+  }
 }
 
-pub async fn download_month(symbol: String, interval: &str, year: i32, month: i32) -> Result<()> {
-  let dl_dir = PathBuf::from("history").join(&symbol);
-  let year = format!("{year}");
+/// Spot archives predate markets and stay at the top of `history/`,
+/// futures ones go under `history/futures/um` and `history/futures/cm`.
+fn market_dir(market: Market) -> PathBuf {
+  match market {
+    Market::Spot => PathBuf::from("history"),
+    _ => PathBuf::from("history").join(market.archive_root()),
+  }
+}
 
-  let month = format!("{month:02}");
-  let dl_dir = dl_dir.join(interval);
+fn archive_dir(market: Market, symbol: &str, series: Series) -> PathBuf {
+  market_dir(market).join(symbol).join(series.to_string())
+}
 
+/// Where an archive is published, relative to the data source's base url.
+fn archive_url(market: Market, symbol: &str, series: Series, period: Period) -> String {
+  let root = market.archive_root();
+  let tree = period.tree();
+  let name = period.name();
+  match series {
+    Series::Klines(interval) => {
+      format!("{root}/{tree}/klines/{symbol}/{interval}/{symbol}-{interval}-{name}.zip")
+    }
+    Series::AggTrades => {
+      format!("{root}/{tree}/aggTrades/{symbol}/{symbol}-aggTrades-{name}.zip")
+    }
+  }
+}
+
+/// Downloads and verifies a single archive.
+async fn download_archive(
+  client: &Client,
+  market: Market,
+  symbol: &str,
+  series: Series,
+  period: Period,
+) -> Result<Status> {
+  let dl_dir = archive_dir(market, symbol, series);
   let _ = create_dir_all(&dl_dir).await;
 
-  let zip = format!("{year}-{month}.zip");
-  let url = format!("{BASEURL}/{symbol}/{interval}/{symbol}-{interval}-{year}-{month}.zip");
+  let url = archive_url(market, symbol, series, period);
 
-  let file_path = dl_dir.join(&zip);
+  // Archives are only moved into place once verified, so an existing file is complete.
+  let file_path = period.path(&dl_dir);
   if file_path.exists() {
-    return Ok(());
+    return Ok(Status::Skipped);
+  }
+
+  let Some(expected) = fetch_checksum(client, &url).await? else {
+    return Ok(Status::NotFound);
+  };
+
+  info!("Downloading {url}...");
+
+  let part_path = checksum::partial_path(&file_path);
+  let Some(actual) = client.download(&url, &part_path).await? else {
+    return Ok(Status::NotFound);
+  };
+
+  if actual != expected {
+    let _ = remove_file(&part_path).await;
+    bail!("Checksum mismatch for {url}: expected {expected}, got {actual}");
   }
 
-  let resp = reqwest::get(url).await?;
-  let status = resp.status();
-  if !status.is_success() {
-    if status.as_u16() == 404 {
-      return Ok(());
+  write(checksum::sidecar_path(&file_path), &expected).await?;
+  rename(&part_path, &file_path).await?;
+
+  Ok(Status::Ok)
+}
+
+/// Fetches the `.CHECKSUM` published next to an archive.
+/// `None` when the archive does not exist.
+async fn fetch_checksum(client: &Client, zip_url: &str) -> Result<Option<String>> {
+  match client.text(&format!("{zip_url}.CHECKSUM")).await? {
+    Some(body) => Ok(Some(checksum::parse_checksum(&body)?)),
+    None => Ok(None),
+  }
+}
+
+/// Re-checks every archive under `history/` against its checksum,
+/// re-downloading the ones that are corrupt or truncated.
+pub async fn verify_history_all(sources: &Sources) -> Result<()> {
+  let client = Client::new(
+    &sources.binance_data_url,
+    VERIFY_CONCURRENCY,
+    DEFAULT_RETRIES,
+  );
+  let (mut ok, mut repaired, mut failed) = (0, 0, 0);
+
+  for market in Market::ALL {
+    let Ok(mut symbols) = read_dir(market_dir(market)).await else {
+      continue;
+    };
+    while let Some(symbol) = symbols.next_entry().await? {
+      let symbol_name = symbol.file_name().to_string_lossy().to_string();
+      // The futures markets are nested in the spot directory.
+      if market == Market::Spot && symbol_name == "futures" {
+        continue;
+      }
+
+      let mut series_dirs = read_dir(symbol.path()).await?;
+      while let Some(series_dir) = series_dirs.next_entry().await? {
+        let name = series_dir.file_name().to_string_lossy().to_string();
+        let Ok(series) = name.parse::<Series>() else {
+          warn!(
+            "Skipping {:?}, not an interval or aggTrades",
+            series_dir.path()
+          );
+          continue;
+        };
+        let mut files = read_dir(series_dir.path()).await?;
+        while let Some(file) = files.next_entry().await? {
+          let path = file.path();
+          let Some(period) = Period::from_file_name(&file.file_name().to_string_lossy()) else {
+            continue;
+          };
+
+          match verify_archive(&client, market, &symbol_name, series, period, &path).await {
+            Ok(true) => ok += 1,
+            Ok(false) => {
+              warn!("{path:?} is corrupt, re-downloading...");
+              let _ = remove_file(&path).await;
+              let _ = remove_file(checksum::sidecar_path(&path)).await;
+              match download_archive(&client, market, &symbol_name, series, period).await {
+                Ok(Status::Ok) => repaired += 1,
+                // The corrupt file is already gone, so anything short of a fresh
+                // download leaves the archive missing.
+                Ok(status) => {
+                  error!("{path:?}: re-download returned {status:?}");
+                  failed += 1;
+                }
+                Err(err) => {
+                  error!("{path:?}: {err:?}");
+                  failed += 1;
+                }
+              }
+            }
+            Err(err) => {
+              error!("{path:?}: {err:?}");
+              failed += 1;
+            }
+          }
+        }
+      }
+    }
+  }
+
+  info!("Verified history: {ok} ok, {repaired} repaired, {failed} failed.");
+  if failed > 0 {
+    bail!("{failed} archives could not be verified");
+  }
+
+  Ok(())
+}
+
+/// Whether an archive matches its checksum. Archives downloaded before checksums
+/// were kept have their sidecar fetched from Binance.
+async fn verify_archive(
+  client: &Client,
+  market: Market,
+  symbol: &str,
+  series: Series,
+  period: Period,
+  path: &Path,
+) -> Result<bool> {
+  let sidecar = checksum::sidecar_path(path);
+  let expected = match read_to_string(&sidecar).await {
+    Ok(body) => checksum::parse_checksum(&body)?,
+    Err(_) => {
+      let url = archive_url(market, symbol, series, period);
+      let Some(expected) = fetch_checksum(client, &url).await? else {
+        bail!("Binance no longer publishes this archive");
+      };
+      write(&sidecar, &expected).await?;
+      expected
     }
-    bail!("Server responded {:?} for {zip}", resp.status());
+  };
+
+  Ok(checksum::sha256_file(path).await? == expected)
+}
+
+/// Prints the gaps and inconsistencies in the stored candles of the selection.
+/// Returns how many problems were found.
+pub async fn audit_history_all(pool: &PgPool, args: &HistoryArgs) -> Result<usize> {
+  let mut problems = 0;
+  for symbol in args.symbols(pool).await? {
+    let audit = CandleAudit::run(pool, args.market, &symbol.symbol, Some(&args.intervals)).await?;
+    audit.print();
+    problems += audit.problems();
   }
 
-  info!("Downloading {symbol}-{interval}-{zip}...");
+  Ok(problems)
+}
 
-  let mut stream = resp.bytes_stream();
-  let mut file = File::create(&file_path).await?;
+/// Resamples the stored `--source` candles of the selection into each of `--intervals`,
+/// in UTC. Only candles with all their source candles stored are built. They replace
+/// earlier resampled candles but never ones loaded from binance, and are replaced
+/// when the binance archives of their interval are loaded.
+pub async fn resample_history_all(pool: &PgPool, args: &ResampleArgs) -> Result<()> {
+  let source = args.source;
+  let from = month_millis(args.history.from.unwrap_or(FIRST_MONTH));
+  let to = month_millis(args.history.to() + Months::new(1)) - 1;
 
-  while let Some(bytes) = stream.next().await {
-    file.write(&bytes?).await?;
+  let (mut resampled, mut mismatched) = (0, 0);
+  for symbol in args.history.symbols(pool).await? {
+    for &interval in &args.history.intervals {
+      if !source.divides(&interval, &Tz::UTC) {
+        bail!("{interval} candles can't be built from {source} candles");
+      }
+
+      let (count, differ) =
+        resample_interval(pool, args, &symbol.symbol, interval, from, to).await?;
+      info!(
+        "Resampled {} {} {source} to {interval}: {count} candles",
+        args.history.market, symbol.symbol
+      );
+      resampled += count;
+      mismatched += differ;
+    }
+  }
+
+  if args.check {
+    info!("Checked {resampled} resampled candles, {mismatched} differ from the stored ones.");
+    if mismatched > 0 {
+      bail!("{mismatched} resampled candles differ from the stored ones");
+    }
   }
 
   Ok(())
 }
+
+/// Pages through the source candles of a symbol, returning the number of
+/// candles resampled and how many of them differ from the stored ones.
+async fn resample_interval(
+  pool: &PgPool,
+  args: &ResampleArgs,
+  symbol: &str,
+  interval: Interval,
+  from: i64,
+  to: i64,
+) -> Result<(usize, usize)> {
+  let market = args.history.market;
+  // Candles missing finer candles would be stored with short volumes.
+  let mut resampler = Resampler::new(interval, Tz::UTC).complete_only();
+
+  let (mut count, mut mismatched) = (0, 0);
+  let mut cursor = from;
+  loop {
+    let page = Candle::fetch_range(
+      pool,
+      market,
+      symbol,
+      args.source,
+      Some(cursor),
+      Some(to),
+      RESAMPLE_BATCH,
+    )
+    .await?;
+    let Some(last) = page.last() else {
+      break;
+    };
+    cursor = last.open_time + 1;
+
+    // A week can start before `from`, it would be missing its first days.
+    let candles: Vec<Candle> = page
+      .iter()
+      .filter_map(|c| resampler.push(c))
+      .filter(|c| c.open_time >= from)
+      .collect();
+    count += candles.len();
+    mismatched += store_resampled(pool, args, &candles).await?;
+  }
+
+  if let Some(last) = resampler.finish().filter(|c| c.open_time >= from) {
+    count += 1;
+    mismatched += store_resampled(pool, args, &[last]).await?;
+  }
+  if resampler.incomplete() > 0 {
+    warn!(
+      "Skipped {} {symbol} {interval} candles missing some of their {} candles",
+      resampler.incomplete(),
+      args.source
+    );
+  }
+
+  Ok((count, mismatched))
+}
+
+/// Stores resampled candles, or with `--check` compares them with the stored ones
+/// and returns how many differ.
+async fn store_resampled(pool: &PgPool, args: &ResampleArgs, candles: &[Candle]) -> Result<usize> {
+  let (Some(first), Some(last)) = (candles.first(), candles.last()) else {
+    return Ok(0);
+  };
+
+  if !args.check {
+    for month in months(month_of(first.open_time), month_of(last.open_time)) {
+      Candle::ensure_partition(pool, month).await?;
+    }
+    let mut tx = pool.begin().await?;
+    Candle::bulk_insert_resampled(&mut tx, candles).await?;
+    tx.commit().await?;
+    return Ok(0);
+  }
+
+  // Skipped incomplete candles can leave more stored candles in the range than
+  // resampled ones.
+  let stored: HashMap<i64, Candle> = Candle::fetch_range(
+    pool,
+    args.history.market,
+    &first.symbol,
+    first.interval,
+    Some(first.open_time),
+    Some(last.open_time),
+    i64::MAX,
+  )
+  .await?
+  .into_iter()
+  .map(|c| (c.open_time, c))
+  .collect();
+
+  // Only candles loaded from binance are worth comparing with.
+  let mut mismatched = 0;
+  for candle in candles {
+    let stored = stored.get(&candle.open_time);
+    if let Some(stored) = stored.filter(|c| c.resampled_from.is_none()) {
+      if !same_candle(candle, stored) {
+        warn!(
+          "{} {} {} differs from the stored candle",
+          candle.symbol, candle.interval, candle.open_time
+        );
+        mismatched += 1;
+      }
+    }
+  }
+
+  Ok(mismatched)
+}
+
+/// Compares every aggregated field, skipping the ones missing from older candles.
+fn same_candle(a: &Candle, b: &Candle) -> bool {
+  fn same<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+    a.zip(b).is_none_or(|(a, b)| a == b)
+  }
+
+  a.open == b.open
+    && a.high == b.high
+    && a.low == b.low
+    && a.close == b.close
+    && a.num_trades == b.num_trades
+    && a.volume == b.volume
+    && a.taker_volume == b.taker_volume
+    && same(a.close_time, b.close_time)
+    && same(a.quote_volume, b.quote_volume)
+    && same(a.taker_quote_volume, b.taker_quote_volume)
+}
+
+fn month_of(millis: i64) -> NaiveDate {
+  first_of_month(
+    DateTime::from_timestamp_millis(millis)
+      .unwrap_or_default()
+      .date_naive(),
+  )
+}
@@ -0,0 +1,167 @@
+use anyhow::{bail, Context, Result};
+use csv::{ReaderBuilder, StringRecord};
+use entity::{AggTrade, Market};
+use std::io::Read;
+
+use super::kline::{field, to_millis};
+
+/// Parses the aggTrades CSVs in Binance archives:
+/// `agg_trade_id, price, quantity, first_trade_id, last_trade_id, transact_time,
+/// is_buyer_maker, is_best_match`. Futures archives have no `is_best_match`.
+pub struct AggTradeParser {
+  market: Market,
+  symbol: String,
+  /// Lines consumed by previous calls to `parse`
+  offset: u64,
+}
+
+impl AggTradeParser {
+  pub fn new(market: Market, symbol: &str) -> Self {
+    Self {
+      market,
+      symbol: symbol.to_string(),
+      offset: 0,
+    }
+  }
+
+  /// Parses newline terminated CSV lines, like `KlineParser::parse`.
+  pub fn parse(&mut self, input: impl Read) -> Result<Vec<AggTrade>> {
+    let mut reader = ReaderBuilder::new()
+      .has_headers(false)
+      .flexible(true)
+      .from_reader(input);
+
+    let mut trades = vec![];
+    let mut record = StringRecord::new();
+    loop {
+      let more = reader
+        .read_record(&mut record)
+        .with_context(|| format!("line {}", self.offset + reader.position().line()))?;
+      if !more {
+        break;
+      }
+      let line = self.offset + record.position().map_or(0, |p| p.line());
+
+      // Futures archives start with a header row.
+      if line == 1 && field::<i64>(&record, 0, "agg_trade_id").is_err() {
+        continue;
+      }
+
+      let trade = self
+        .trade(&record)
+        .with_context(|| format!("line {line}"))?;
+      trades.push(trade);
+    }
+
+    self.offset += reader.position().line().saturating_sub(1);
+    Ok(trades)
+  }
+
+  fn trade(&self, record: &StringRecord) -> Result<AggTrade> {
+    Ok(AggTrade {
+      market: self.market,
+      symbol: self.symbol.clone(),
+      agg_trade_id: field(record, 0, "agg_trade_id")?,
+      price: field(record, 1, "price")?,
+      quantity: field(record, 2, "quantity")?,
+      first_trade_id: field(record, 3, "first_trade_id")?,
+      last_trade_id: field(record, 4, "last_trade_id")?,
+      transact_time: to_millis(field(record, 5, "transact_time")?),
+      is_buyer_maker: bool_field(record, 6, "is_buyer_maker")?,
+    })
+  }
+}
+
+/// Spot archives write `True`/`False`, futures ones `true`/`false`.
+fn bool_field(record: &StringRecord, index: usize, name: &str) -> Result<bool> {
+  let value: String = field(record, index, name)?;
+  match value.to_lowercase().as_str() {
+    "true" => Ok(true),
+    "false" => Ok(false),
+    _ => bail!("invalid {name} {value:?}: expected true or false"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rust_decimal::Decimal;
+
+  const SPOT: &[u8] = include_bytes!("fixtures/agg_trades_spot.csv");
+  const FUTURES: &[u8] = include_bytes!("fixtures/agg_trades_futures.csv");
+  const MICROS: &[u8] = include_bytes!("fixtures/agg_trades_micros.csv");
+  const MALFORMED: &[u8] = include_bytes!("fixtures/agg_trades_malformed.csv");
+
+  fn dec(value: &str) -> Decimal {
+    value.parse().unwrap()
+  }
+
+  /// The error of a failed parse, with its context.
+  fn error(result: Result<Vec<AggTrade>>) -> String {
+    match result {
+      Ok(trades) => panic!("parsed {} trades, expected an error", trades.len()),
+      Err(err) => format!("{err:#}"),
+    }
+  }
+
+  #[test]
+  fn parses_spot_archives() {
+    let trades = AggTradeParser::new(Market::Spot, "ETHBTC")
+      .parse(SPOT)
+      .unwrap();
+    assert_eq!(trades.len(), 3);
+
+    let first = &trades[0];
+    assert_eq!(first.market, Market::Spot);
+    assert_eq!(first.symbol, "ETHBTC");
+    assert_eq!(first.agg_trade_id, 26129);
+    assert_eq!(first.price, dec("0.01633102"));
+    assert_eq!(first.quantity, dec("4.70443515"));
+    assert_eq!(first.first_trade_id, 27781);
+    assert_eq!(first.last_trade_id, 27781);
+    assert_eq!(first.transact_time, 1498793709153);
+    assert!(first.is_buyer_maker);
+    assert!(!trades[1].is_buyer_maker);
+    assert_eq!(trades[2].last_trade_id, 27784);
+  }
+
+  #[test]
+  fn parses_futures_archives() {
+    let trades = AggTradeParser::new(Market::UsdM, "BTCUSDT")
+      .parse(FUTURES)
+      .unwrap();
+    // The header row is skipped, and there is no `is_best_match` column.
+    assert_eq!(trades.len(), 2);
+    assert_eq!(trades[0].agg_trade_id, 2189431548);
+    assert_eq!(trades[0].price, dec("42314"));
+    assert_eq!(trades[0].transact_time, 1704067200071);
+    assert!(!trades[0].is_buyer_maker);
+    assert!(trades[1].is_buyer_maker);
+  }
+
+  #[test]
+  fn only_skips_a_header_on_the_first_line_of_the_file() {
+    let mut parser = AggTradeParser::new(Market::UsdM, "BTCUSDT");
+    assert_eq!(parser.parse(FUTURES).unwrap().len(), 2);
+    let header = &FUTURES[..=FUTURES.iter().position(|b| *b == b'\n').unwrap()];
+    assert!(error(parser.parse(header)).starts_with("line 4: invalid agg_trade_id"));
+  }
+
+  #[test]
+  fn normalizes_microsecond_timestamps() {
+    let trades = AggTradeParser::new(Market::Spot, "BTCUSDT")
+      .parse(MICROS)
+      .unwrap();
+    assert_eq!(trades.len(), 2);
+    assert_eq!(trades[0].transact_time, 1735689600014);
+    assert_eq!(trades[1].transact_time, 1735689600015);
+  }
+
+  #[test]
+  fn reports_malformed_flags() {
+    assert_eq!(
+      error(AggTradeParser::new(Market::Spot, "ETHBTC").parse(MALFORMED)),
+      "line 2: invalid is_buyer_maker \"yes\": expected true or false"
+    );
+  }
+}
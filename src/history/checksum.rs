@@ -0,0 +1,53 @@
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::{fs::File, io::AsyncReadExt};
+
+/// Hex encoded SHA-256 digest of a file on disk.
+pub async fn sha256_file(path: &Path) -> Result<String> {
+  let mut file = File::open(path).await?;
+  let mut hasher = Sha256::new();
+  let mut buf = vec![0; 64 * 1024];
+
+  loop {
+    let read = file.read(&mut buf).await?;
+    if read == 0 {
+      break;
+    }
+    hasher.update(&buf[..read]);
+  }
+
+  Ok(hex(hasher))
+}
+
+pub fn hex(hasher: Sha256) -> String {
+  format!("{:x}", hasher.finalize())
+}
+
+/// Binance publishes `<sha256>  <file name>` next to every archive.
+pub fn parse_checksum(body: &str) -> Result<String> {
+  let Some(digest) = body.split_whitespace().next() else {
+    bail!("Empty checksum file");
+  };
+  if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+    bail!("Malformed checksum {body:?}");
+  }
+
+  Ok(digest.to_ascii_lowercase())
+}
+
+/// Where the expected checksum of an archive is kept: `2024-01.zip.CHECKSUM`
+pub fn sidecar_path(zip_path: &Path) -> PathBuf {
+  with_suffix(zip_path, ".CHECKSUM")
+}
+
+/// Where an archive is written while it is being downloaded.
+pub fn partial_path(zip_path: &Path) -> PathBuf {
+  with_suffix(zip_path, ".part")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+  let mut path = path.as_os_str().to_owned();
+  path.push(suffix);
+  PathBuf::from(path)
+}
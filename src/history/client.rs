@@ -0,0 +1,310 @@
+use anyhow::{anyhow, bail, Result};
+use futures::{Future, StreamExt};
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use std::{
+  io::ErrorKind,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
+use tokio::{
+  fs::{copy, read_to_string, File},
+  io::AsyncWriteExt,
+  sync::Semaphore,
+  time::sleep,
+};
+use tracing::warn;
+
+use super::checksum;
+
+pub const DEFAULT_RETRIES: u32 = 5;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Client shared by every download of a job. Over HTTP it caps the requests in flight
+/// and retries 429s, 5xxs and connection errors with jittered exponential backoff.
+/// A `file://` url or a directory as the base url copies from a local mirror instead.
+#[derive(Clone)]
+pub struct Client {
+  http: reqwest::Client,
+  base_url: String,
+  mirror: Option<PathBuf>,
+  permits: Arc<Semaphore>,
+  retries: u32,
+}
+
+impl Client {
+  /// `concurrency` is the number of requests in flight, at least 1.
+  pub fn new(base_url: &str, concurrency: usize, retries: u32) -> Self {
+    let base_url = base_url.trim_end_matches('/').to_string();
+    let mirror = match base_url.strip_prefix("file://") {
+      Some(dir) => Some(PathBuf::from(dir)),
+      None if !base_url.starts_with("http://") && !base_url.starts_with("https://") => {
+        Some(PathBuf::from(&base_url))
+      }
+      None => None,
+    };
+
+    Self {
+      http: reqwest::Client::new(),
+      base_url,
+      mirror,
+      permits: Arc::new(Semaphore::new(concurrency)),
+      retries,
+    }
+  }
+
+  /// The body of `path`, `None` when it doesn't exist.
+  pub async fn text(&self, path: &str) -> Result<Option<String>> {
+    if let Some(mirror) = &self.mirror {
+      return match read_to_string(mirror.join(path)).await {
+        Ok(body) => Ok(Some(body)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+      };
+    }
+
+    self
+      .fetch(path, |resp| async move {
+        Ok::<_, anyhow::Error>(resp.text().await?)
+      })
+      .await
+  }
+
+  /// Streams `path` into `dest`, returning the SHA-256 of what was written.
+  /// `None` when it doesn't exist.
+  pub async fn download(&self, path: &str, dest: &Path) -> Result<Option<String>> {
+    if let Some(mirror) = &self.mirror {
+      return match copy(mirror.join(path), dest).await {
+        Ok(_) => Ok(Some(checksum::sha256_file(dest).await?)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+      };
+    }
+
+    self
+      .fetch(path, |resp| async move {
+        let mut file = File::create(dest).await?;
+        let mut hasher = Sha256::new();
+        let mut stream = resp.bytes_stream();
+
+        while let Some(bytes) = stream.next().await {
+          let bytes = bytes?;
+          hasher.update(&bytes);
+          file.write_all(&bytes).await?;
+        }
+        file.sync_all().await?;
+
+        Ok::<_, anyhow::Error>(checksum::hex(hasher))
+      })
+      .await
+  }
+
+  /// GETs `path` and hands the response to `read`, retrying the whole exchange
+  /// when either fails transiently.
+  async fn fetch<T, F, Fut>(&self, path: &str, read: F) -> Result<Option<T>>
+  where
+    F: Fn(Response) -> Fut,
+    Fut: Future<Output = Result<T>>,
+  {
+    let url = format!("{}/{path}", self.base_url);
+
+    let mut attempt = 0;
+    loop {
+      let permit = self.permits.acquire().await?;
+      let (delay, err) = match self.http.get(&url).send().await {
+        Ok(resp) if resp.status() == StatusCode::NOT_FOUND => return Ok(None),
+        Ok(resp) if resp.status().is_success() => match read(resp).await {
+          Ok(value) => return Ok(Some(value)),
+          Err(err) => (backoff(attempt), err),
+        },
+        Ok(resp) if is_retryable(resp.status()) => (
+          retry_after(&resp).unwrap_or_else(|| backoff(attempt)),
+          anyhow!("Server responded {:?} for {url}", resp.status()),
+        ),
+        Ok(resp) => bail!("Server responded {:?} for {url}", resp.status()),
+        Err(err) if err.is_connect() || err.is_timeout() || err.is_request() => {
+          (backoff(attempt), err.into())
+        }
+        Err(err) => return Err(err.into()),
+      };
+      drop(permit);
+
+      if attempt >= self.retries {
+        return Err(err.context(format!("Gave up after {} attempts", attempt + 1)));
+      }
+      warn!("{err:#}, retrying in {delay:?}");
+      sleep(delay).await;
+      attempt += 1;
+    }
+  }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+  status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// `Retry-After` in seconds, the only form data.binance.vision sends.
+fn retry_after(resp: &Response) -> Option<Duration> {
+  let seconds = resp
+    .headers()
+    .get(RETRY_AFTER)?
+    .to_str()
+    .ok()?
+    .parse()
+    .ok()?;
+  Some(Duration::from_secs(seconds).min(MAX_DELAY))
+}
+
+/// Exponential backoff with jitter, so retries from concurrent downloads spread out.
+fn backoff(attempt: u32) -> Duration {
+  let delay = BASE_DELAY
+    .saturating_mul(2u32.saturating_pow(attempt))
+    .min(MAX_DELAY);
+  delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+  };
+  use futures::future::join_all;
+  use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+  };
+  use tokio::net::TcpListener;
+
+  /// Serves `app` on a free local port, returning its url.
+  async fn serve(app: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    url
+  }
+
+  /// Answers GETs of `/file` with `responses` in order, then `200 data`. Returns the
+  /// url and the number of requests served.
+  async fn mock(
+    responses: &'static [(StatusCode, Option<&'static str>)],
+  ) -> (String, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let count = requests.clone();
+    let app = Router::new().route(
+      "/file",
+      get(move || async move {
+        let (status, retry_after) = responses
+          .get(count.fetch_add(1, Ordering::SeqCst))
+          .copied()
+          .unwrap_or((StatusCode::OK, None));
+        let mut resp: Response = (status, "data").into_response();
+        if let Some(seconds) = retry_after {
+          resp
+            .headers_mut()
+            .insert(header::RETRY_AFTER, seconds.parse().unwrap());
+        }
+        resp
+      }),
+    );
+    (serve(app).await, requests)
+  }
+
+  #[tokio::test]
+  async fn retries_server_errors() {
+    let (url, requests) = mock(&[
+      (StatusCode::INTERNAL_SERVER_ERROR, None),
+      (StatusCode::TOO_MANY_REQUESTS, Some("0")),
+    ])
+    .await;
+    let client = Client::new(&url, 1, 2);
+    assert_eq!(client.text("file").await.unwrap().as_deref(), Some("data"));
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+  }
+
+  #[tokio::test]
+  async fn waits_for_retry_after() {
+    let (url, requests) = mock(&[(StatusCode::SERVICE_UNAVAILABLE, Some("1"))]).await;
+    let client = Client::new(&url, 1, 1);
+    let start = Instant::now();
+    assert_eq!(client.text("file").await.unwrap().as_deref(), Some("data"));
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+  }
+
+  #[tokio::test]
+  async fn gives_up_after_the_retries() {
+    const UNAVAILABLE: (StatusCode, Option<&str>) = (StatusCode::SERVICE_UNAVAILABLE, Some("0"));
+    let (url, requests) = mock(&[UNAVAILABLE; 4]).await;
+    let client = Client::new(&url, 1, 2);
+    let err = client.text("file").await.unwrap_err();
+    assert!(format!("{err:#}").starts_with("Gave up after 3 attempts: Server responded 503"));
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+  }
+
+  #[tokio::test]
+  async fn returns_none_for_missing_files() {
+    let (url, requests) = mock(&[(StatusCode::NOT_FOUND, None)]).await;
+    let client = Client::new(&url, 1, 2);
+    assert_eq!(client.text("file").await.unwrap(), None);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    let dest = std::env::temp_dir().join(format!("copper-client-{}", std::process::id()));
+    assert_eq!(client.download("missing", &dest).await.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn fails_client_errors_without_retrying() {
+    let (url, requests) = mock(&[(StatusCode::FORBIDDEN, None)]).await;
+    let client = Client::new(&url, 1, 2);
+    let err = client.text("file").await.unwrap_err();
+    assert!(err.to_string().starts_with("Server responded 403"));
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+  }
+
+  #[tokio::test]
+  async fn downloads_and_hashes_files() {
+    let (url, _) = mock(&[]).await;
+    let client = Client::new(&url, 1, 0);
+    let dest = std::env::temp_dir().join(format!("copper-client-{}.zip", std::process::id()));
+    let sha256 = client.download("file", &dest).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&dest).unwrap(), "data");
+    std::fs::remove_file(&dest).unwrap();
+    assert_eq!(
+      sha256.as_deref(),
+      Some("3a6eb0790f39ac87c94f3856b2dd2c5d110e6811602261a9a923d3bb23adc8b7")
+    );
+  }
+
+  #[tokio::test]
+  async fn caps_the_requests_in_flight() {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let app = Router::new().route(
+      "/file",
+      get({
+        let (in_flight, peak) = (in_flight.clone(), peak.clone());
+        move || async move {
+          let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+          peak.fetch_max(now, Ordering::SeqCst);
+          sleep(Duration::from_millis(50)).await;
+          in_flight.fetch_sub(1, Ordering::SeqCst);
+          "data"
+        }
+      }),
+    );
+    let client = Client::new(&serve(app).await, 2, 0);
+
+    let bodies = join_all((0..8).map(|_| client.text("file"))).await;
+    assert!(bodies
+      .iter()
+      .all(|body| body.as_ref().unwrap().as_deref() == Some("data")));
+    assert_eq!(peak.load(Ordering::SeqCst), 2);
+  }
+}
@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Context, Result};
+use csv::{ReaderBuilder, StringRecord};
+use entity::{Candle, Interval, Market};
+use std::{fmt::Display, io::Read, str::FromStr};
+
+/// Timestamps at or above this are microseconds. Binance switched spot archives
+/// to microseconds in 2025, millisecond timestamps won't get here for millennia.
+const MICROS_THRESHOLD: i64 = 100_000_000_000_000;
+
+/// Parses the kline CSVs in Binance spot and futures archives:
+/// `open_time, open, high, low, close, volume, close_time, quote_volume,
+/// num_trades, taker_volume, taker_quote_volume, ignore`
+pub struct KlineParser {
+  market: Market,
+  symbol: String,
+  interval: Interval,
+  /// Lines consumed by previous calls to `parse`
+  offset: u64,
+}
+
+impl KlineParser {
+  pub fn new(market: Market, symbol: &str, interval: Interval) -> Self {
+    Self {
+      market,
+      symbol: symbol.to_string(),
+      interval,
+      offset: 0,
+    }
+  }
+
+  /// Parses newline terminated CSV lines. Consecutive chunks of the same file can be
+  /// passed in separate calls, line numbers in errors count from the start of the file.
+  pub fn parse(&mut self, input: impl Read) -> Result<Vec<Candle>> {
+    let mut reader = ReaderBuilder::new()
+      .has_headers(false)
+      .flexible(true)
+      .from_reader(input);
+
+    let mut candles = vec![];
+    let mut record = StringRecord::new();
+    loop {
+      let more = reader
+        .read_record(&mut record)
+        .with_context(|| format!("line {}", self.offset + reader.position().line()))?;
+      if !more {
+        break;
+      }
+      let line = self.offset + record.position().map_or(0, |p| p.line());
+
+      // Newer archives start with a header row.
+      if line == 1 && field::<i64>(&record, 0, "open_time").is_err() {
+        continue;
+      }
+
+      let candle = self
+        .candle(&record)
+        .with_context(|| format!("line {line}"))?;
+      candles.push(candle);
+    }
+
+    self.offset += reader.position().line().saturating_sub(1);
+    Ok(candles)
+  }
+
+  fn candle(&self, record: &StringRecord) -> Result<Candle> {
+    Ok(Candle {
+      market: self.market,
+      symbol: self.symbol.clone(),
+      interval: self.interval,
+      open_time: to_millis(field(record, 0, "open_time")?),
+      open: field(record, 1, "open")?,
+      high: field(record, 2, "high")?,
+      low: field(record, 3, "low")?,
+      close: field(record, 4, "close")?,
+      volume: field(record, 5, "volume")?,
+      close_time: Some(to_millis(field(record, 6, "close_time")?)),
+      quote_volume: Some(field(record, 7, "quote_volume")?),
+      num_trades: field(record, 8, "num_trades")?,
+      taker_volume: field(record, 9, "taker_volume")?,
+      taker_quote_volume: Some(field(record, 10, "taker_quote_volume")?),
+      resampled_from: None,
+    })
+  }
+}
+
+pub(super) fn field<T>(record: &StringRecord, index: usize, name: &str) -> Result<T>
+where
+  T: FromStr,
+  T::Err: Display,
+{
+  let value = record
+    .get(index)
+    .ok_or_else(|| anyhow!("missing {name} (column {})", index + 1))?;
+  value
+    .trim()
+    .parse()
+    .map_err(|err| anyhow!("invalid {name} {value:?}: {err}"))
+}
+
+pub(super) fn to_millis(timestamp: i64) -> i64 {
+  if timestamp >= MICROS_THRESHOLD {
+    timestamp / 1000
+  } else {
+    timestamp
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rust_decimal::Decimal;
+
+  const HEADERLESS: &[u8] = include_bytes!("fixtures/klines_headerless.csv");
+  const HEADER: &[u8] = include_bytes!("fixtures/klines_header.csv");
+  const MICROS: &[u8] = include_bytes!("fixtures/klines_micros.csv");
+  const SHORT_LINE: &[u8] = include_bytes!("fixtures/klines_short_line.csv");
+  const MALFORMED: &[u8] = include_bytes!("fixtures/klines_malformed.csv");
+
+  fn parser() -> KlineParser {
+    KlineParser::new(Market::Spot, "BTCUSDT", Interval::Hours(1))
+  }
+
+  fn dec(value: &str) -> Decimal {
+    value.parse().unwrap()
+  }
+
+  /// The error of a failed parse, with its context.
+  fn error(result: Result<Vec<Candle>>) -> String {
+    match result {
+      Ok(candles) => panic!("parsed {} candles, expected an error", candles.len()),
+      Err(err) => format!("{err:#}"),
+    }
+  }
+
+  /// Splits a fixture into batches of `sizes` lines, the way the loader reads archives.
+  fn batches<'a>(file: &'a [u8], sizes: &[usize]) -> Vec<&'a [u8]> {
+    let mut batches = vec![];
+    let mut rest = file;
+    for size in sizes {
+      let end = rest
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(size - 1)
+        .map_or(rest.len(), |(i, _)| i + 1);
+      let (batch, tail) = rest.split_at(end);
+      batches.push(batch);
+      rest = tail;
+    }
+    batches.push(rest);
+    batches
+  }
+
+  #[test]
+  fn parses_headerless_archives() {
+    let candles = parser().parse(HEADERLESS).unwrap();
+    assert_eq!(candles.len(), 3);
+
+    let first = &candles[0];
+    assert_eq!(first.symbol, "BTCUSDT");
+    assert_eq!(first.interval, Interval::Hours(1));
+    assert_eq!(first.open_time, 1502942400000);
+    assert_eq!(first.close_time, Some(1502945999999));
+    assert_eq!(first.open, dec("4261.48"));
+    assert_eq!(first.high, dec("4313.62"));
+    assert_eq!(first.low, dec("4261.32"));
+    assert_eq!(first.close, dec("4308.83"));
+    assert_eq!(first.volume, dec("47.181009"));
+    assert_eq!(first.quote_volume, Some(dec("202366.13839304")));
+    assert_eq!(first.num_trades, 171);
+    assert_eq!(first.taker_volume, dec("35.160503"));
+    assert_eq!(first.taker_quote_volume, Some(dec("150952.47794304")));
+  }
+
+  #[test]
+  fn skips_the_header_row() {
+    let candles = parser().parse(HEADER).unwrap();
+    assert_eq!(candles.len(), 2);
+    assert_eq!(candles[0].open_time, 1704067200000);
+    assert_eq!(candles[0].open, dec("42314"));
+  }
+
+  #[test]
+  fn only_skips_a_header_on_the_first_line_of_the_file() {
+    let mut parser = parser();
+    let [first, second, rest] = batches(HEADER, &[2, 1])[..] else {
+      panic!("expected 3 batches");
+    };
+    assert_eq!(parser.parse(first).unwrap().len(), 1);
+    assert_eq!(parser.parse(second).unwrap().len(), 1);
+    assert!(parser.parse(rest).unwrap().is_empty());
+
+    let header = batches(HEADER, &[1])[0];
+    assert!(error(parser.parse(header)).starts_with("line 4: invalid open_time"));
+  }
+
+  #[test]
+  fn normalizes_microsecond_timestamps() {
+    let candles = parser().parse(MICROS).unwrap();
+    assert_eq!(candles.len(), 2);
+    assert_eq!(candles[0].open_time, 1735689600000);
+    assert_eq!(candles[0].close_time, Some(1735693199999));
+    assert_eq!(candles[1].open_time, 1735693200000);
+    assert_eq!(candles[1].close_time, Some(1735696799999));
+  }
+
+  #[test]
+  fn reports_short_lines() {
+    assert_eq!(
+      error(parser().parse(SHORT_LINE)),
+      "line 3: missing quote_volume (column 8)"
+    );
+  }
+
+  #[test]
+  fn reports_malformed_values() {
+    assert!(error(parser().parse(MALFORMED)).starts_with("line 4: invalid open \"n/a\""));
+  }
+
+  #[test]
+  fn counts_lines_across_batches() {
+    let mut parser = parser();
+    let [first, second, rest] = batches(MALFORMED, &[2, 1])[..] else {
+      panic!("expected 3 batches");
+    };
+    assert_eq!(parser.parse(first).unwrap().len(), 2);
+    assert_eq!(parser.parse(second).unwrap().len(), 1);
+    assert!(error(parser.parse(rest)).starts_with("line 4: invalid open \"n/a\""));
+
+    let mut parser = self::parser();
+    let [first, rest] = batches(SHORT_LINE, &[1])[..] else {
+      panic!("expected 2 batches");
+    };
+    assert_eq!(parser.parse(first).unwrap().len(), 1);
+    assert_eq!(
+      error(parser.parse(rest)),
+      "line 3: missing quote_volume (column 8)"
+    );
+  }
+}
@@ -0,0 +1,61 @@
+use chrono::{Datelike, Months, NaiveDate};
+use std::path::{Path, PathBuf};
+
+/// Binance publishes archives per calendar month, and per day for recent data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+  /// The first day of the month
+  Month(NaiveDate),
+  Day(NaiveDate),
+}
+
+impl Period {
+  /// The archive tree this period is published under.
+  pub fn tree(&self) -> &'static str {
+    match self {
+      Self::Month(_) => "monthly",
+      Self::Day(_) => "daily",
+    }
+  }
+
+  /// `2024-01` for months, `2024-01-31` for days.
+  pub fn name(&self) -> String {
+    match self {
+      Self::Month(date) => date.format("%Y-%m").to_string(),
+      Self::Day(date) => date.format("%Y-%m-%d").to_string(),
+    }
+  }
+
+  /// Where the archive of this period is kept in an interval's directory.
+  pub fn path(&self, dir: &Path) -> PathBuf {
+    dir.join(format!("{}.zip", self.name()))
+  }
+
+  /// Parses an archive file name, `2024-01.zip` or `2024-01-31.zip`.
+  pub fn from_file_name(name: &str) -> Option<Self> {
+    let stem = name.strip_suffix(".zip")?;
+    if let Ok(day) = NaiveDate::parse_from_str(stem, "%Y-%m-%d") {
+      return Some(Self::Day(day));
+    }
+    let month = NaiveDate::parse_from_str(&format!("{stem}-01"), "%Y-%m-%d").ok()?;
+    Some(Self::Month(month))
+  }
+}
+
+pub fn first_of_month(date: NaiveDate) -> NaiveDate {
+  date.with_day(1).unwrap()
+}
+
+/// Every month from `from` through `to`, as the first day of the month.
+pub fn months(from: NaiveDate, to: NaiveDate) -> impl Iterator<Item = NaiveDate> {
+  let to = first_of_month(to);
+  std::iter::successors(Some(first_of_month(from)), |m| {
+    m.checked_add_months(Months::new(1))
+  })
+  .take_while(move |m| *m <= to)
+}
+
+/// Every day from `from` through `to`.
+pub fn days(from: NaiveDate, to: NaiveDate) -> impl Iterator<Item = NaiveDate> {
+  from.iter_days().take_while(move |d| *d <= to)
+}
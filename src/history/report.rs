@@ -0,0 +1,105 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::{path::Path, process::ExitCode};
+
+/// What happened to a single archive.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+  Ok,
+  /// Already downloaded or loaded
+  Skipped,
+  /// Binance doesn't publish the archive
+  NotFound,
+  Failed,
+}
+
+impl Status {
+  const ALL: [Self; 4] = [Self::Ok, Self::Skipped, Self::NotFound, Self::Failed];
+
+  fn label(&self) -> &'static str {
+    match self {
+      Self::Ok => "ok",
+      Self::Skipped => "skipped",
+      Self::NotFound => "not found",
+      Self::Failed => "failed",
+    }
+  }
+}
+
+#[derive(Serialize)]
+pub struct Outcome {
+  pub file: String,
+  pub status: Status,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub reason: Option<String>,
+}
+
+/// Per-file outcomes of a history job. Concurrent tasks each fill their own
+/// report, which are then merged with `extend`.
+#[derive(Serialize, Default)]
+pub struct Report {
+  job: &'static str,
+  outcomes: Vec<Outcome>,
+}
+
+impl Report {
+  pub fn new(job: &'static str) -> Self {
+    Self {
+      job,
+      outcomes: vec![],
+    }
+  }
+
+  pub fn record(&mut self, file: impl AsRef<Path>, status: Status) {
+    self.push(file, status, None);
+  }
+
+  pub fn fail(&mut self, file: impl AsRef<Path>, err: &anyhow::Error) {
+    self.push(file, Status::Failed, Some(format!("{err:#}")));
+  }
+
+  pub fn extend(&mut self, other: Report) {
+    self.outcomes.extend(other.outcomes);
+  }
+
+  fn push(&mut self, file: impl AsRef<Path>, status: Status, reason: Option<String>) {
+    self.outcomes.push(Outcome {
+      file: file.as_ref().to_string_lossy().to_string(),
+      status,
+      reason,
+    });
+  }
+
+  fn count(&self, status: Status) -> usize {
+    self.outcomes.iter().filter(|o| o.status == status).count()
+  }
+
+  /// Prints the summary table, writes the JSON report when asked to, and
+  /// exits with 3 when any file failed.
+  pub async fn finish(&self, json_path: Option<&Path>) -> Result<ExitCode> {
+    println!("{}", self.job);
+    for status in Status::ALL {
+      println!("  {:<10} {:>8}", status.label(), self.count(status));
+    }
+
+    let failed = self.count(Status::Failed);
+    if failed > 0 {
+      println!("Failures:");
+      for outcome in &self.outcomes {
+        if let Some(reason) = &outcome.reason {
+          println!("  {}: {reason}", outcome.file);
+        }
+      }
+    }
+
+    if let Some(json_path) = json_path {
+      tokio::fs::write(json_path, serde_json::to_vec_pretty(self)?).await?;
+    }
+
+    Ok(match failed {
+      0 => ExitCode::SUCCESS,
+      _ => ExitCode::from(3),
+    })
+  }
+}
@@ -1,17 +1,23 @@
 #![feature(try_trait_v2)]
+#![feature(try_trait_v2_residual)]
 
 use anyhow::Result;
-use clap::Parser;
-use entity::Symbol;
+use clap::{Parser, Subcommand};
+use config::Sources;
+use entity::{Market, Symbol};
+use std::process::ExitCode;
+use tracing::{error, info};
 
 mod api;
+mod audit;
 mod config;
 mod db;
 mod history;
 mod prelude;
+mod resample;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
   let args = Args::parse();
 
   tracing_subscriber::fmt()
@@ -20,42 +26,144 @@ async fn main() -> Result<()> {
     .with_max_level(tracing::Level::INFO)
     .init();
 
-  let pool = db::pool().await?;
-
-  if args.populate_symbols {
-    let mut tx = pool.begin().await?;
-    Symbol::populate_all(&mut tx).await?;
-    tx.commit().await?;
-    return Ok(());
-  }
-
-  if args.download_history {
-    history::download_history_all(&pool).await?;
-    return Ok(());
+  match run(args.command).await {
+    Ok(code) => code,
+    Err(err) => {
+      error!("{err:?}");
+      ExitCode::FAILURE
+    }
   }
+}
 
-  if args.load_history {
-    history::load_btc_usdt(&pool).await?;
-    return Ok(());
+async fn run(command: Command) -> Result<ExitCode> {
+  match command {
+    Command::Serve { migrate } => api::serve(migrate).await?,
+    Command::Symbols(SymbolsCommand::Sync { markets }) => {
+      let pool = db::pool().await?;
+      let sources = Sources::init();
+      for market in markets {
+        let mut tx = pool.begin().await?;
+        let summary = Symbol::sync_all(&mut tx, market, sources.api_url(market)).await?;
+        tx.commit().await?;
+        info!(
+          "Synced {market} symbols: {} added, {} changed, {} delisted.",
+          summary.added.len(),
+          summary.changed.len(),
+          summary.delisted.len()
+        );
+      }
+    }
+    Command::History(HistoryCommand::Download(args)) => {
+      let pool = db::pool_for(args.concurrency).await?;
+      let report = history::download_history_all(&pool, &Sources::init(), &args).await?;
+      return report.finish(args.report.as_deref()).await;
+    }
+    Command::History(HistoryCommand::Load(args)) => {
+      let pool = db::pool_for(args.concurrency).await?;
+      let report = history::load_history_all(&pool, &args).await?;
+      return report.finish(args.report.as_deref()).await;
+    }
+    Command::History(HistoryCommand::Status(args)) => {
+      let pool = db::pool().await?;
+      history::report_history_status(&pool, &args).await?;
+    }
+    Command::History(HistoryCommand::Audit(args)) => {
+      let pool = db::pool().await?;
+      let problems = history::audit_history_all(&pool, &args).await?;
+      if problems > 0 {
+        error!("Found {problems} problems in the stored candles");
+        return Ok(ExitCode::from(3));
+      }
+    }
+    Command::History(HistoryCommand::Resample(args)) => {
+      let pool = db::pool().await?;
+      history::resample_history_all(&pool, &args).await?;
+    }
+    Command::History(HistoryCommand::Verify) => {
+      history::verify_history_all(&Sources::init()).await?
+    }
+    Command::Db(DbCommand::Migrate) => {
+      let pool = db::pool().await?;
+      db::migrate(&pool).await?;
+    }
+    Command::Db(DbCommand::Partitions) => {
+      let pool = db::pool().await?;
+      db::check_partition_pruning(&pool).await?;
+    }
   }
 
-  api::serve().await.unwrap();
-
-  Ok(())
+  Ok(ExitCode::SUCCESS)
 }
 
 #[derive(Parser, Debug)]
-#[command(version)]
+#[command(
+  version,
+  after_help = "Exit codes: 0 on success, 1 when the command failed, 2 on invalid usage, \
+    3 when a history job finished but some files failed or an audit found problems."
+)]
 struct Args {
-  /// Populate the trading symbols from binance
-  #[arg(long)]
-  populate_symbols: bool,
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Serve the API
+  Serve {
+    /// Apply pending migrations before serving
+    #[arg(long)]
+    migrate: bool,
+  },
+
+  /// Trading symbols
+  #[command(subcommand)]
+  Symbols(SymbolsCommand),
+
+  /// Historical candle and trade data
+  #[command(subcommand)]
+  History(HistoryCommand),
+
+  /// Database maintenance
+  #[command(subcommand)]
+  Db(DbCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum SymbolsCommand {
+  /// Sync the trading symbols from binance, delisting the ones that are gone
+  Sync {
+    /// Markets to sync (comma separated): spot, um (USDⓈ-M futures) or cm (COIN-M futures)
+    #[arg(long, value_delimiter = ',', default_value = "spot")]
+    markets: Vec<Market>,
+  },
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryCommand {
+  /// Download historical data for the selected symbols
+  Download(history::HistoryArgs),
+
+  /// Load the downloaded archives into the database
+  Load(history::HistoryArgs),
+
+  /// List the downloaded archives that are not loaded yet or failed to load
+  Status(history::HistoryArgs),
+
+  /// Check downloaded archives against their checksums and re-download corrupt ones
+  Verify,
+
+  /// Report gaps, invalid candles and inconsistencies between intervals in the stored candles
+  Audit(history::HistoryArgs),
+
+  /// Build candles of other intervals from finer loaded candles
+  Resample(history::ResampleArgs),
+}
 
-  /// Download historical data for all of the symbols in the database
-  #[arg(long)]
-  download_history: bool,
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+  /// Apply pending migrations
+  Migrate,
 
-  /// Load the downloaded candle data into the database
-  #[arg(long)]
-  load_history: bool,
+  /// Check that range queries on candles only scan the partitions they need
+  Partitions,
 }
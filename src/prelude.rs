@@ -5,7 +5,7 @@ pub use crate::{
 pub use anyhow::Result;
 pub use axum::{
   body::Body,
-  extract::{Path, State},
+  extract::{Path, Query, State},
   http::StatusCode,
   response::{IntoResponse, Response},
   Extension, Json,
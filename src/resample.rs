@@ -0,0 +1,233 @@
+use chrono_tz::Tz;
+use entity::{Candle, Interval};
+use rust_decimal::Decimal;
+
+/// The binance intervals `target` can be resampled from in `tz`, coarsest first.
+pub fn sources(target: &Interval, tz: Tz) -> Vec<Interval> {
+  Interval::BINANCE
+    .into_iter()
+    .rev()
+    .filter(|interval| interval.divides(target, &tz))
+    .collect()
+}
+
+/// Folds finer candles, pushed in `open_time` order, into candles of a coarser interval.
+pub struct Resampler {
+  interval: Interval,
+  tz: Tz,
+  current: Option<Candle>,
+  /// The finer candles folded into `current`
+  folded: i64,
+  complete_only: bool,
+  incomplete: usize,
+}
+
+impl Resampler {
+  pub fn new(interval: Interval, tz: Tz) -> Self {
+    Self {
+      interval,
+      tz,
+      current: None,
+      folded: 0,
+      complete_only: false,
+      incomplete: 0,
+    }
+  }
+
+  /// Drops the candles missing some of their finer candles, instead of building
+  /// them from the ones there are.
+  pub fn complete_only(mut self) -> Self {
+    self.complete_only = true;
+    self
+  }
+
+  /// How many candles `complete_only` dropped before the last one.
+  pub fn incomplete(&self) -> usize {
+    self.incomplete
+  }
+
+  /// Adds a candle, returning the previous candle once it is complete.
+  pub fn push(&mut self, candle: &Candle) -> Option<Candle> {
+    let start = self.interval.open_time_in(candle.open_time, &self.tz);
+    if let Some(current) = self.current.as_mut().filter(|c| c.open_time == start) {
+      current.high = current.high.max(candle.high);
+      current.low = current.low.min(candle.low);
+      current.close = candle.close;
+      current.num_trades += candle.num_trades;
+      current.volume += candle.volume;
+      current.taker_volume += candle.taker_volume;
+      current.quote_volume = add_opt(current.quote_volume, candle.quote_volume);
+      current.taker_quote_volume = add_opt(current.taker_quote_volume, candle.taker_quote_volume);
+      self.folded += 1;
+      return None;
+    }
+
+    let next = Candle {
+      market: candle.market,
+      symbol: candle.symbol.clone(),
+      interval: self.interval,
+      open_time: start,
+      close_time: Some(self.interval.close_time_in(start, &self.tz)),
+      open: candle.open,
+      close: candle.close,
+      high: candle.high,
+      low: candle.low,
+      num_trades: candle.num_trades,
+      volume: candle.volume,
+      taker_volume: candle.taker_volume,
+      quote_volume: candle.quote_volume,
+      taker_quote_volume: candle.taker_quote_volume,
+      resampled_from: Some(candle.interval),
+    };
+    let previous = self.current.replace(next);
+    let folded = std::mem::replace(&mut self.folded, 1);
+    previous.filter(|c| self.keep(c, folded))
+  }
+
+  /// The last candle, which without `complete_only` may be missing some of its
+  /// finer candles. It isn't counted as `incomplete`, the finer candles usually
+  /// just end before it does.
+  pub fn finish(&mut self) -> Option<Candle> {
+    let last = self.current.take();
+    let folded = std::mem::take(&mut self.folded);
+    last.filter(|c| !self.complete_only || folded == self.expected(c))
+  }
+
+  fn keep(&mut self, candle: &Candle, folded: i64) -> bool {
+    if !self.complete_only || folded == self.expected(candle) {
+      return true;
+    }
+    self.incomplete += 1;
+    false
+  }
+
+  /// How many finer candles make up `candle` when none are missing.
+  fn expected(&self, candle: &Candle) -> i64 {
+    let Some(source) = candle.resampled_from else {
+      return 0;
+    };
+    let end = candle.close_time.unwrap_or(candle.open_time) + 1;
+    // Finer candles are stored in UTC, where all but months have a fixed length.
+    if let Some((step, _)) = source.alignment() {
+      return (end - candle.open_time) / step;
+    }
+    let mut count = 0;
+    let mut time = candle.open_time;
+    while time < end {
+      count += 1;
+      time = source.next_open_time_in(time, &self.tz);
+    }
+    count
+  }
+}
+
+/// Quote volumes are unknown for candles loaded before they were kept.
+fn add_opt(a: Option<Decimal>, b: Option<Decimal>) -> Option<Decimal> {
+  Some(a? + b?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::history::KlineParser;
+  use chrono_tz::{Asia::Kolkata, Europe::Berlin};
+  use entity::Market;
+
+  /// 2024-03-30 and 2024-03-31 UTC, Berlin switches to summer time on the 31st.
+  const HOURS: &[u8] = include_bytes!("history/fixtures/klines_1h.csv");
+  const DAYS: &[u8] = include_bytes!("history/fixtures/klines_1d.csv");
+  /// The 23h day of 2024-03-31 in Berlin.
+  const BERLIN_DAY: &[u8] = include_bytes!("history/fixtures/klines_1d_berlin.csv");
+
+  fn parse(interval: Interval, file: &[u8]) -> Vec<Candle> {
+    KlineParser::new(Market::Spot, "BTCUSDT", interval)
+      .parse(file)
+      .unwrap()
+  }
+
+  fn resample(interval: Interval, tz: Tz, candles: &[Candle]) -> Vec<Candle> {
+    let mut resampler = Resampler::new(interval, tz);
+    let mut resampled: Vec<Candle> = candles.iter().filter_map(|c| resampler.push(c)).collect();
+    resampled.extend(resampler.finish());
+    resampled
+  }
+
+  fn assert_same(resampled: &Candle, expected: &Candle) {
+    let fields = |c: &Candle| {
+      (
+        c.interval,
+        c.open_time,
+        c.close_time,
+        [c.open, c.high, c.low, c.close, c.volume, c.taker_volume],
+        c.num_trades,
+        c.quote_volume,
+        c.taker_quote_volume,
+      )
+    };
+    assert_eq!(fields(resampled), fields(expected));
+    assert_eq!(resampled.resampled_from, Some(Interval::Hours(1)));
+  }
+
+  #[test]
+  fn lists_sources_coarsest_first() {
+    let day = Interval::Days(1);
+    assert_eq!(
+      sources(&day, Tz::UTC)[..3],
+      [day, Interval::Hours(12), Interval::Hours(8)]
+    );
+    assert_eq!(sources(&day, Tz::UTC).last(), Some(&Interval::Seconds(1)));
+    assert_eq!(sources(&day, Berlin)[0], Interval::Hours(1));
+    assert_eq!(sources(&day, Kolkata)[0], Interval::Minutes(30));
+    assert_eq!(sources(&Interval::Hours(5), Tz::UTC)[0], Interval::Hours(1));
+    assert!(sources(&Interval::Months(1), Berlin).contains(&Interval::Hours(1)));
+    assert!(!sources(&Interval::Weeks(1), Berlin).contains(&day));
+  }
+
+  #[test]
+  fn resamples_hours_into_binance_days() {
+    let days = resample(
+      Interval::Days(1),
+      Tz::UTC,
+      &parse(Interval::Hours(1), HOURS),
+    );
+    let expected = parse(Interval::Days(1), DAYS);
+    assert_eq!(days.len(), expected.len());
+    for (day, expected) in days.iter().zip(&expected) {
+      assert_same(day, expected);
+    }
+  }
+
+  #[test]
+  fn resamples_hours_into_dst_days() {
+    let days = resample(Interval::Days(1), Berlin, &parse(Interval::Hours(1), HOURS));
+    // The fixture starts an hour into the 30th in Berlin and ends two hours into
+    // April 1st, so only the 31st is complete.
+    assert_eq!(days.len(), 3);
+    let [expected] = &parse(Interval::Days(1), BERLIN_DAY)[..] else {
+      panic!("expected one day");
+    };
+    assert_same(&days[1], expected);
+    assert_eq!(days[2].open_time, expected.close_time.unwrap() + 1);
+  }
+
+  #[test]
+  fn drops_candles_missing_finer_candles() {
+    let mut hours = parse(Interval::Hours(1), HOURS);
+    hours.remove(10);
+    let mut resampler = Resampler::new(Interval::Days(1), Tz::UTC).complete_only();
+    let mut days: Vec<Candle> = hours.iter().filter_map(|c| resampler.push(c)).collect();
+    days.extend(resampler.finish());
+    assert_eq!(days.len(), 1);
+    assert_same(&days[0], &parse(Interval::Days(1), DAYS)[1]);
+    assert_eq!(resampler.incomplete(), 1);
+
+    // Only the 23h day is complete in Berlin, the partial last day isn't counted.
+    let mut resampler = Resampler::new(Interval::Days(1), Berlin).complete_only();
+    let hours = parse(Interval::Hours(1), HOURS);
+    let mut days: Vec<Candle> = hours.iter().filter_map(|c| resampler.push(c)).collect();
+    days.extend(resampler.finish());
+    assert_eq!(days.len(), 1);
+    assert_same(&days[0], &parse(Interval::Days(1), BERLIN_DAY)[0]);
+    assert_eq!(resampler.incomplete(), 1);
+  }
+}